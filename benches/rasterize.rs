@@ -0,0 +1,91 @@
+#[macro_use]
+extern crate criterion;
+extern crate image;
+extern crate ae;
+
+use std::io::Cursor;
+use criterion::{Criterion, Throughput};
+
+use ae::format::pal::Palette;
+use ae::format::slp::*;
+
+/// A unit-sized frame with the mix of color lists, fills, player colors,
+/// shadows and outlines found in game sprites.
+fn frame(width: u32, height: u32) -> SlpFrame {
+    let mut pixels = vec![SlpPixel::Transparent; (width * height) as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as i32 - width as i32 / 2, y as i32 - height as i32 / 2);
+            let d = dx * dx + dy * dy;
+            let r = (width as i32 / 2) * (width as i32 / 2);
+
+            pixels[(y * width + x) as usize] = if d > r {
+                SlpPixel::Transparent
+            } else if d > r * 9 / 10 {
+                SlpPixel::BlackOutline
+            } else if dy > height as i32 / 4 {
+                SlpPixel::Shadow
+            } else if dx.abs() < 4 {
+                SlpPixel::Player((y % 8) as u8)
+            } else if x % 16 < 8 {
+                SlpPixel::Color(((x * 7 + y * 3) % 200) as u8 + 1)
+            } else {
+                SlpPixel::Color(100)
+            };
+        }
+    }
+
+    SlpFrame {
+        width: width,
+        height: height,
+        hotspot: (width as i32 / 2, height as i32 / 2),
+        pixels: pixels.into_boxed_slice()
+    }
+}
+
+fn bench_rasterize(c: &mut Criterion) {
+    let (width, height) = (128, 128);
+
+    let mut writer = SlpWriter::new();
+    writer.add_frame(frame(width, height));
+    let mut data = Vec::new();
+    writer.write(&mut data).unwrap();
+
+    let obj = SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap();
+    let pal = Palette {
+        colors: (0..256).map(|i| (i as u8, (i * 3) as u8, (i * 7) as u8)).collect::<Vec<_>>().into_boxed_slice()
+    };
+    let options = SlpRasterOptions::default();
+    let table = SlpColorTable::new(&pal, &options).unwrap();
+
+    let mut group = c.benchmark_group("rasterize");
+    group.throughput(Throughput::Bytes((width * height * 4) as u64));
+
+    group.bench_function("default", |b| {
+        let mut cursor = Cursor::new(vec![0u8; (width * height * 4) as usize]);
+        b.iter(|| {
+            cursor.set_position(0);
+            SlpDefaultRasterizer::rasterize_with(&mut cursor, &obj, &pal, &options).unwrap();
+        })
+    });
+
+    group.bench_function("draw", |b| {
+        let mut image = image::RgbaImage::new(width, height);
+        b.iter(|| SlpDefaultRasterizer::draw(&mut image, &obj, &pal, &options, 0, 0, SlpBlendMode::Replace).unwrap())
+    });
+
+    group.bench_function("fast", |b| {
+        let mut out = vec![0u8; (width * height * 4) as usize];
+        b.iter(|| SlpFastRasterizer::rasterize(&mut out, width as usize * 4, &obj, &table).unwrap())
+    });
+
+    group.bench_function("layered", |b| {
+        b.iter(|| SlpLayeredRasterizer::rasterize(&obj, &pal).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_rasterize);
+criterion_main!(benches);
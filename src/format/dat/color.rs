@@ -29,4 +29,18 @@ impl PlayerColor {
 
         Ok(col)
     }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Palette index of the first color of the player's band.
+    pub fn palette_base(&self) -> i32 {
+        self.palette
+    }
+
+    /// Palette index used for unit outlines.
+    pub fn outline_color(&self) -> i32 {
+        self.color
+    }
 }
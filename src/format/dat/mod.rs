@@ -41,4 +41,8 @@ impl GameDataFile {
             colors: colors
         })
     }
+
+    pub fn player_colors(&self) -> &[PlayerColor] {
+        &self.colors
+    }
 }
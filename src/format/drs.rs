@@ -1,6 +1,12 @@
-use byteorder::{LittleEndian, BigEndian, ReadBytesExt};
+use byteorder::{LittleEndian, BigEndian, ReadBytesExt, WriteBytesExt};
 
-use std::io::{Read, Write, Seek, SeekFrom, Cursor};
+use memmap::Mmap;
+
+use format::sniff::{sniff, ContentType};
+
+use std::io::{Read, Write, Seek, SeekFrom, Cursor, ErrorKind};
+use std::fs::File;
+use std::path::Path;
 use std::error;
 use std::fmt;
 
@@ -12,10 +18,88 @@ pub enum DrsFile {
     Unknown(u32, Box<[u8]>)
 }
 
+/// Type of the files stored in a DRS table, decoded from the packed
+/// extension in the table info.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DrsExt {
+    Bin,
+    Slp,
+    Wav,
+    Unknown(u32)
+}
+
+impl DrsExt {
+    pub fn from_u32(ext: u32) -> DrsExt {
+        match ext & 0xffffff00 {
+            0x62696e00 => DrsExt::Bin,
+            0x736c7000 => DrsExt::Slp,
+            0x77617600 => DrsExt::Wav,
+            _ => DrsExt::Unknown(ext)
+        }
+    }
+
+    /// Packed extension as written by the original archives, e.g.
+    /// `0x736c7020` for `slp`.
+    pub fn to_u32(self) -> u32 {
+        match self {
+            DrsExt::Bin => 0x62696e61,
+            DrsExt::Slp => 0x736c7020,
+            DrsExt::Wav => 0x77617620,
+            DrsExt::Unknown(ext) => ext
+        }
+    }
+
+    /// File extension used for this type, e.g. `slp` or `bina`.
+    pub fn name(self) -> String {
+        ext_to_string(self.to_u32())
+    }
+}
+
+impl From<u32> for DrsExt {
+    fn from(ext: u32) -> Self {
+        DrsExt::from_u32(ext)
+    }
+}
+
+impl DrsFile {
+    pub fn ext(&self) -> DrsExt {
+        match *self {
+            DrsFile::Wav(_) => DrsExt::Wav,
+            DrsFile::Bin(_) => DrsExt::Bin,
+            DrsFile::Slp(_) => DrsExt::Slp,
+            DrsFile::Unknown(ext, _) => DrsExt::Unknown(ext)
+        }
+    }
+
+    /// Detects the format of the file from its contents rather than the
+    /// table it was stored in.
+    pub fn sniff(&self) -> ContentType {
+        match *self {
+            DrsFile::Wav(ref data) | DrsFile::Bin(ref data) | DrsFile::Slp(ref data) | DrsFile::Unknown(_, ref data) => sniff(data)
+        }
+    }
+
+    pub fn new(ext: DrsExt, data: Box<[u8]>) -> DrsFile {
+        match ext {
+            DrsExt::Bin => DrsFile::Bin(data),
+            DrsExt::Slp => DrsFile::Slp(data),
+            DrsExt::Wav => DrsFile::Wav(data),
+            DrsExt::Unknown(ext) => DrsFile::Unknown(ext, data)
+        }
+    }
+
+    pub fn into_data(self) -> Box<[u8]> {
+        match self {
+            DrsFile::Wav(data) | DrsFile::Bin(data) | DrsFile::Slp(data) | DrsFile::Unknown(_, data) => data
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum DrsError {
     Io(::std::io::Error),
     TableNotFound(i32),
+    ExtNotFound(DrsExt),
     EntryNotFound(i32)
 }
 
@@ -30,6 +114,7 @@ impl fmt::Display for DrsError {
         match *self {
             DrsError::Io(ref err) => write!(f, "IO error: {}", err),
             DrsError::TableNotFound(ref table) => write!(f, "Table {} not found", table),
+            DrsError::ExtNotFound(ref ext) => write!(f, "Table for {:?} not found", ext),
             DrsError::EntryNotFound(ref entry) => write!(f, "Entry {} not found", entry)
         }
     }
@@ -40,6 +125,7 @@ impl error::Error for DrsError {
         match *self {
             DrsError::Io(ref err) => err.description(),
             DrsError::TableNotFound(ref table) => "DRS table not found",
+            DrsError::ExtNotFound(ref ext) => "DRS table not found",
             DrsError::EntryNotFound(ref entry) => "DRS entry not found"
         }
     }
@@ -52,10 +138,37 @@ impl error::Error for DrsError {
     }
 }
 
-#[repr(C)]
-#[derive(Debug)]
+/// Game the archive was made for, as detected from its header.
+///
+/// Detection looks at the length of the copyright, found by checking
+/// whether the bytes after the first 40 look like a version such as
+/// `1.00`, and at the archive type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DrsVariant {
+    /// Age of Empires, Rise of Rome and Age of Kings, which share a 40 byte
+    /// copyright and the `tribe` archive type. Their headers only differ in
+    /// the copyright text, so these games can't be told apart and all
+    /// report `Tribe`.
+    Tribe,
+    /// Star Wars: Galactic Battlegrounds, with a 60 byte copyright and the
+    /// `swbg` archive type.
+    StarWars,
+    Unknown
+}
+
+impl DrsVariant {
+    fn copyright_len(self) -> usize {
+        match self {
+            DrsVariant::StarWars => 60,
+            _ => 40
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Header {
-    copyright: [[u8; 20]; 2],
+    /// 40 bytes, or 60 for Star Wars: Galactic Battlegrounds.
+    copyright: Vec<u8>,
     version: [u8; 4],
     ty: [u8; 12],
     tables: u32,
@@ -70,24 +183,71 @@ struct TableInfo {
     size: u32
 }
 
+/// Location of a single file inside a DRS archive.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct TableEntry {
-    id: u32,
-    offset: u32,
-    size: u32
+pub struct TableEntry {
+    pub id: u32,
+    pub offset: u32,
+    pub size: u32
+}
+
+/// A table of a DRS archive, holding all files of one extension.
+#[derive(Debug, Clone)]
+pub struct DrsTable<'a> {
+    pub ext: String,
+    pub kind: DrsExt,
+    pub entries: &'a [TableEntry]
 }
 
+impl<'a> DrsTable<'a> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+type Tables = (Vec<TableInfo>, Vec<Vec<TableEntry>>);
+
 pub struct DrsReader<R: Read + Seek> {
     reader: R,
-    tables: (Vec<TableInfo>, Vec<Vec<TableEntry>>)
+    header: Header,
+    tables: Tables,
+    /// Length of the stream, read once when opening so that reading
+    /// entries doesn't seek to the end and discard buffered data.
+    len: u64
+}
+
+impl Header {
+    fn size(&self) -> u32 {
+        self.copyright.len() as u32 + 4 + 12 + 4 + 4
+    }
+
+    fn variant(&self) -> DrsVariant {
+        match self.ty().as_str() {
+            "tribe" if self.copyright.len() == 40 => DrsVariant::Tribe,
+            "swbg" if self.copyright.len() == 60 => DrsVariant::StarWars,
+            _ => DrsVariant::Unknown
+        }
+    }
+
+    fn copyright(&self) -> String {
+        String::from_utf8_lossy(&self.copyright).trim_right_matches(|c| c == '\0' || c == '\x1a').to_string()
+    }
+
+    fn version(&self) -> String {
+        String::from_utf8_lossy(&self.version).trim_right_matches('\0').to_string()
+    }
+
+    fn ty(&self) -> String {
+        String::from_utf8_lossy(&self.ty).trim_right_matches('\0').to_string()
+    }
 }
 
 impl<R: Read + Seek> DrsReader<R> {
     pub fn new(mut reader: R) -> Result<DrsReader<R>, DrsError> {
         let header = try!(read_drs_header(&mut reader).map_err(DrsError::Io));
         //println!("{:#?}", header);
-        let table_infos = try!(read_drs_table_info(header, &mut reader).map_err(DrsError::Io));
+        let table_infos = try!(read_drs_table_info(&header, &mut reader).map_err(DrsError::Io));
         //println!("{:#?}", table_infos);
         let tables = try!(read_drs_tables(&table_infos, &mut reader).map_err(DrsError::Io));
 
@@ -99,54 +259,718 @@ impl<R: Read + Seek> DrsReader<R> {
         }*/
         //println!("{:#?}", tables);
 
+        let len = try!(reader.seek(SeekFrom::End(0)).map_err(DrsError::Io));
+
         Ok(DrsReader {
             reader: reader,
-            tables: (table_infos, tables)
+            header: header,
+            tables: (table_infos, tables),
+            len: len
         })
     }
 
+    /// Lists all tables of the archive along with their entries.
+    pub fn tables(&self) -> Vec<DrsTable> {
+        list_tables(&self.tables)
+    }
+
+    pub fn variant(&self) -> DrsVariant {
+        self.header.variant()
+    }
+
+    /// Copyright notice from the header, e.g.
+    /// `Copyright (c) 1997 Ensemble Studios.`
+    pub fn copyright(&self) -> String {
+        self.header.copyright()
+    }
+
+    /// Version string from the header, e.g. `1.00`.
+    pub fn version(&self) -> String {
+        self.header.version()
+    }
+
+    /// Archive type from the header, e.g. `tribe`.
+    pub fn archive_type(&self) -> String {
+        self.header.ty()
+    }
+
+    /// Checks the archive for structural problems such as entries that
+    /// overlap or extend past the end of the file, and ids that are unsorted
+    /// or duplicated within a table, which break lookups by id.
+    ///
+    /// Archives whose tables are cut short by the end of the file can still
+    /// be opened, so that this reports them instead of `new` failing.
+    pub fn validate(&mut self) -> Result<DrsReport, DrsError> {
+        Ok(validate_tables(&self.header, &self.tables, self.len))
+    }
+
+    /// Returns an iterator that reads every file of the archive in table
+    /// order, yielding the table extension and id along with the file.
+    pub fn files(&mut self) -> DrsFiles<R> {
+        DrsFiles {
+            reader: self,
+            table: 0,
+            entry: 0
+        }
+    }
+
+    /// Returns the first table holding files of the given type.
+    pub fn table(&self, ext: DrsExt) -> Option<DrsTable> {
+        find_table(&self.tables, ext)
+    }
+
+    /// Position of the first table holding files of the given type, usable
+    /// with `read_file`.
+    pub fn table_index(&self, ext: DrsExt) -> Option<usize> {
+        table_index(&self.tables, ext)
+    }
+
+    /// Reads a file by id from the table holding files of the given type.
+    pub fn read_file_ext(&mut self, ext: DrsExt, id: usize) -> Result<DrsFile, DrsError> {
+        let table = try!(self.table_index(ext).ok_or(DrsError::ExtNotFound(ext)));
+
+        self.read_file(table, id)
+    }
+
+    /// Reads a file by id from whichever table contains it, searching the
+    /// tables in order.
+    pub fn find_file(&mut self, id: usize) -> Result<DrsFile, DrsError> {
+        let (ext, entry) = try!(find_entry(&self.tables, id));
+
+        self.read_entry(ext, entry)
+    }
+
     pub fn read_file(&mut self, table: usize, id: usize) -> Result<DrsFile, DrsError> {
-        let ext = try!(self.tables.0.get(table).ok_or(DrsError::TableNotFound(table as _))).ext;
+        let (ext, entry) = try!(lookup_entry(&self.tables, table, id));
+
+        self.read_entry(ext, entry)
+    }
+
+    /// Opens a file for streaming instead of reading it into memory. The
+    /// returned reader only sees the file's bytes, so it can be handed to
+    /// anything taking a `Read + Seek`, such as `SlpReader::new`.
+    pub fn open_file(&mut self, table: usize, id: usize) -> Result<DrsEntryReader<R>, DrsError> {
+        let (_, entry) = try!(lookup_entry(&self.tables, table, id));
+
+        DrsEntryReader::new(&mut self.reader, entry)
+    }
+
+    /// Opens a file for streaming from the table holding files of the given
+    /// type, see `open_file`.
+    pub fn open_file_ext(&mut self, ext: DrsExt, id: usize) -> Result<DrsEntryReader<R>, DrsError> {
+        let table = try!(self.table_index(ext).ok_or(DrsError::ExtNotFound(ext)));
+
+        self.open_file(table, id)
+    }
+
+    fn read_entry(&mut self, ext: u32, entry: TableEntry) -> Result<DrsFile, DrsError> {
+        // Check the size before allocating, so a bogus entry can't make us
+        // allocate more than the archive holds.
+        if entry.offset as u64 + entry.size as u64 > self.len {
+            return Err(DrsError::Io(::std::io::Error::new(ErrorKind::UnexpectedEof, "DRS entry extends past end of archive")));
+        }
+
+        try!(self.reader.seek(SeekFrom::Start(entry.offset as _)));
+        let mut data = vec![0u8; entry.size as _].into_boxed_slice();
+        try!(self.reader.read_exact(&mut data));
+
+        Ok(DrsFile::new(DrsExt::from_u32(ext), data))
+    }
+}
+
+/// Reader over a single file inside a DRS archive, see
+/// `DrsReader::open_file`. Positions are relative to the start of the file.
+pub struct DrsEntryReader<'a, R: 'a + Read + Seek> {
+    reader: &'a mut R,
+    start: u64,
+    len: u64,
+    pos: u64
+}
+
+impl<'a, R: Read + Seek> DrsEntryReader<'a, R> {
+    fn new(reader: &'a mut R, entry: TableEntry) -> Result<DrsEntryReader<'a, R>, DrsError> {
+        try!(reader.seek(SeekFrom::Start(entry.offset as _)));
+
+        Ok(DrsEntryReader {
+            reader: reader,
+            start: entry.offset as _,
+            len: entry.size as _,
+            pos: 0
+        })
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl<'a, R: Read + Seek> Read for DrsEntryReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let max = ::std::cmp::min(buf.len() as u64, self.len - self.pos) as usize;
+        let read = try!(self.reader.read(&mut buf[..max]));
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for DrsEntryReader<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> ::std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.len as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset
+        };
+
+        if pos < 0 {
+            return Err(::std::io::Error::new(ErrorKind::InvalidInput, "seek before start of DRS entry"));
+        }
+
+        try!(self.reader.seek(SeekFrom::Start(self.start + pos as u64)));
+        self.pos = pos as u64;
+
+        Ok(self.pos)
+    }
+}
+
+/// Iterator over all files of a `DrsReader`, see `DrsReader::files`.
+pub struct DrsFiles<'a, R: 'a + Read + Seek> {
+    reader: &'a mut DrsReader<R>,
+    table: usize,
+    entry: usize
+}
+
+impl<'a, R: Read + Seek> Iterator for DrsFiles<'a, R> {
+    type Item = Result<(String, u32, DrsFile), DrsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.table < self.reader.tables.0.len() {
+            let ext = self.reader.tables.0[self.table].ext;
+
+            if let Some(&entry) = self.reader.tables.1[self.table].get(self.entry) {
+                self.entry += 1;
+
+                return Some(self.reader.read_entry(ext, entry).map(|file| {
+                    (ext_to_string(ext), entry.id, file)
+                }));
+            }
+
+            self.table += 1;
+            self.entry = 0;
+        }
+
+        None
+    }
+}
+
+/// A DRS archive held in memory, typically a memory-mapped file.
+///
+/// Unlike `DrsReader` lookups don't need mutable access and return slices
+/// borrowed from the archive, so a single archive can be shared between
+/// threads.
+pub struct DrsArchive<B: AsRef<[u8]>> {
+    data: B,
+    header: Header,
+    tables: Tables
+}
+
+impl DrsArchive<Mmap> {
+    /// Memory-maps the archive at `path`.
+    ///
+    /// The file must not be modified while it is mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<DrsArchive<Mmap>, DrsError> {
+        let file = try!(File::open(path));
+        let data = try!(unsafe { Mmap::map(&file) });
+
+        DrsArchive::new(data)
+    }
+}
+
+impl<B: AsRef<[u8]>> DrsArchive<B> {
+    pub fn new(data: B) -> Result<DrsArchive<B>, DrsError> {
+        let (header, tables) = {
+            let mut reader = Cursor::new(data.as_ref());
+            let header = try!(read_drs_header(&mut reader));
+            let table_infos = try!(read_drs_table_info(&header, &mut reader));
+            let tables = try!(read_drs_tables(&table_infos, &mut reader));
+
+            (header, (table_infos, tables))
+        };
+
+        Ok(DrsArchive {
+            data: data,
+            header: header,
+            tables: tables
+        })
+    }
+
+    /// Lists all tables of the archive along with their entries.
+    pub fn tables(&self) -> Vec<DrsTable> {
+        list_tables(&self.tables)
+    }
+
+    pub fn variant(&self) -> DrsVariant {
+        self.header.variant()
+    }
+
+    /// Copyright notice from the header, e.g.
+    /// `Copyright (c) 1997 Ensemble Studios.`
+    pub fn copyright(&self) -> String {
+        self.header.copyright()
+    }
+
+    /// Version string from the header, e.g. `1.00`.
+    pub fn version(&self) -> String {
+        self.header.version()
+    }
+
+    /// Archive type from the header, e.g. `tribe`.
+    pub fn archive_type(&self) -> String {
+        self.header.ty()
+    }
+
+    /// Checks the archive for structural problems, see `DrsReader::validate`.
+    pub fn validate(&self) -> DrsReport {
+        validate_tables(&self.header, &self.tables, self.data.as_ref().len() as _)
+    }
+
+    /// Returns the first table holding files of the given type.
+    pub fn table(&self, ext: DrsExt) -> Option<DrsTable> {
+        find_table(&self.tables, ext)
+    }
+
+    /// Position of the first table holding files of the given type, usable
+    /// with `get`.
+    pub fn table_index(&self, ext: DrsExt) -> Option<usize> {
+        table_index(&self.tables, ext)
+    }
+
+    pub fn get(&self, table: usize, id: usize) -> Result<&[u8], DrsError> {
+        let (_, entry) = try!(lookup_entry(&self.tables, table, id));
+
+        self.slice(entry)
+    }
+
+    /// Looks up a file by id in the table holding files of the given type.
+    pub fn get_ext(&self, ext: DrsExt, id: usize) -> Result<&[u8], DrsError> {
+        let table = try!(self.table_index(ext).ok_or(DrsError::ExtNotFound(ext)));
+
+        self.get(table, id)
+    }
+
+    /// Looks up a file by id in whichever table contains it, searching the
+    /// tables in order.
+    pub fn find(&self, id: usize) -> Result<(DrsExt, &[u8]), DrsError> {
+        let (ext, entry) = try!(find_entry(&self.tables, id));
+
+        self.slice(entry).map(|data| (DrsExt::from_u32(ext), data))
+    }
+
+    fn slice(&self, entry: TableEntry) -> Result<&[u8], DrsError> {
+        let data = self.data.as_ref();
+
+        // Checked in u64 first, so the usize offsets below can't overflow.
+        if entry.offset as u64 + entry.size as u64 > data.len() as u64 {
+            return Err(DrsError::Io(::std::io::Error::new(ErrorKind::UnexpectedEof, "DRS entry extends past end of archive")));
+        }
+
+        let start = entry.offset as usize;
+        Ok(&data[start..start + entry.size as usize])
+    }
+}
+
+/// A problem found by `DrsReader::validate`. Tables are identified by their
+/// position in the archive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrsIssue {
+    UnknownVersion(String),
+    UnknownType(String),
+    /// The header's data offset doesn't match the end of the entry tables.
+    DataOffsetMismatch { expected: u32, found: u32 },
+    /// The header declares more tables than the file has room for; only
+    /// the ones that fit were read.
+    TableInfoPastEof { declared: u32, found: usize },
+    /// The table's entries run past the end of the file; only the ones
+    /// that fit were read.
+    TablePastEof { table: usize },
+    UnknownExtension { table: usize, ext: u32 },
+    UnsortedIds { table: usize, id: u32, previous: u32 },
+    DuplicateId { table: usize, id: u32 },
+    EntryPastEof { table: usize, id: u32, offset: u32, size: u32 },
+    /// The entry's data starts inside the header or entry tables.
+    EntryInDirectory { table: usize, id: u32, offset: u32 },
+    OverlappingEntries { first: (usize, u32), second: (usize, u32) }
+}
+
+impl fmt::Display for DrsIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DrsIssue::UnknownVersion(ref version) => write!(f, "unknown version {:?}", version),
+            DrsIssue::UnknownType(ref ty) => write!(f, "unknown archive type {:?}", ty),
+            DrsIssue::DataOffsetMismatch { expected, found } => write!(f, "data offset is {}, expected {}", found, expected),
+            DrsIssue::TableInfoPastEof { declared, found } => write!(f, "header declares {} tables but only {} fit in the file", declared, found),
+            DrsIssue::TablePastEof { table } => write!(f, "table {} extends past end of file", table),
+            DrsIssue::UnknownExtension { table, ext } => write!(f, "table {} has unknown extension 0x{:08x}", table, ext),
+            DrsIssue::UnsortedIds { table, id, previous } => write!(f, "table {}: entry {} follows entry {}", table, id, previous),
+            DrsIssue::DuplicateId { table, id } => write!(f, "table {}: duplicate entry {}", table, id),
+            DrsIssue::EntryPastEof { table, id, offset, size } => write!(f, "table {}: entry {} ({} bytes at {}) extends past end of file", table, id, size, offset),
+            DrsIssue::EntryInDirectory { table, id, offset } => write!(f, "table {}: entry {} at {} starts inside the entry tables", table, id, offset),
+            DrsIssue::OverlappingEntries { first, second } => write!(f, "table {} entry {} overlaps table {} entry {}", first.0, first.1, second.0, second.1)
+        }
+    }
+}
+
+/// Result of validating a DRS archive.
+#[derive(Debug, Clone)]
+pub struct DrsReport {
+    pub issues: Vec<DrsIssue>
+}
+
+impl DrsReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
 
-        if let Ok(key) = self.tables.1[table].binary_search_by_key(&id, |&e| e.id as _) {
-            let entry = self.tables.1[table][key];
+fn validate_tables(header: &Header, tables: &Tables, len: u64) -> DrsReport {
+    let mut issues = Vec::new();
 
-            try!(self.reader.seek(SeekFrom::Start(entry.offset as _)));
-            let mut data = {
-                let mut vec = Vec::with_capacity(entry.size as _);
-                unsafe {
-                    vec.set_len(entry.size as _);
+    let version = header.version();
+    if version != "1.00" {
+        issues.push(DrsIssue::UnknownVersion(version));
+    }
+
+    if header.variant() == DrsVariant::Unknown {
+        issues.push(DrsIssue::UnknownType(header.ty()));
+    }
+
+    if (tables.0.len() as u64) < header.tables as u64 {
+        issues.push(DrsIssue::TableInfoPastEof {
+            declared: header.tables,
+            found: tables.0.len()
+        });
+    }
+
+    let entry_count = tables.1.iter().map(|entries| entries.len() as u64).sum::<u64>();
+    let directory_end = header.size() as u64
+        + tables.0.len() as u64 * DRS_TABLE_INFO_SIZE as u64
+        + entry_count * DRS_TABLE_ENTRY_SIZE as u64;
+
+    if header.offset as u64 != directory_end {
+        issues.push(DrsIssue::DataOffsetMismatch {
+            expected: directory_end as _,
+            found: header.offset
+        });
+    }
+
+    let mut spans = Vec::new();
+    for (table, (info, entries)) in tables.0.iter().zip(tables.1.iter()).enumerate() {
+        if let DrsExt::Unknown(ext) = DrsExt::from_u32(info.ext) {
+            issues.push(DrsIssue::UnknownExtension { table: table, ext: ext });
+        }
+
+        if info.offset as u64 + info.size as u64 * DRS_TABLE_ENTRY_SIZE as u64 > len {
+            issues.push(DrsIssue::TablePastEof { table: table });
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                let previous = entries[i - 1].id;
+                if entry.id == previous {
+                    issues.push(DrsIssue::DuplicateId { table: table, id: entry.id });
+                } else if entry.id < previous {
+                    issues.push(DrsIssue::UnsortedIds { table: table, id: entry.id, previous: previous });
                 }
-                vec.into_boxed_slice()
-            };
-            try!(self.reader.read_exact(&mut data));
+            }
+
+            if (entry.offset as u64) < directory_end {
+                issues.push(DrsIssue::EntryInDirectory { table: table, id: entry.id, offset: entry.offset });
+            }
+
+            if entry.offset as u64 + entry.size as u64 > len {
+                issues.push(DrsIssue::EntryPastEof {
+                    table: table,
+                    id: entry.id,
+                    offset: entry.offset,
+                    size: entry.size
+                });
+            }
+
+            if entry.size > 0 {
+                spans.push((entry.offset as u64, entry.offset as u64 + entry.size as u64, table, entry.id));
+            }
+        }
+    }
+
+    spans.sort();
+    let mut furthest: Option<(u64, usize, u32)> = None;
+    for &(start, end, table, id) in spans.iter() {
+        if let Some((prev_end, prev_table, prev_id)) = furthest {
+            if start < prev_end {
+                issues.push(DrsIssue::OverlappingEntries {
+                    first: (prev_table, prev_id),
+                    second: (table, id)
+                });
+            }
+
+            if end <= prev_end {
+                continue;
+            }
+        }
+
+        furthest = Some((end, table, id));
+    }
+
+    DrsReport {
+        issues: issues
+    }
+}
+
+fn list_tables(tables: &Tables) -> Vec<DrsTable> {
+    tables.0.iter().zip(tables.1.iter()).map(|(info, entries)| {
+        DrsTable {
+            ext: ext_to_string(info.ext),
+            kind: DrsExt::from_u32(info.ext),
+            entries: entries
+        }
+    }).collect()
+}
+
+fn table_index(tables: &Tables, ext: DrsExt) -> Option<usize> {
+    tables.0.iter().position(|info| DrsExt::from_u32(info.ext) == ext)
+}
+
+fn find_table(tables: &Tables, ext: DrsExt) -> Option<DrsTable> {
+    table_index(tables, ext).map(|table| {
+        DrsTable {
+            ext: ext_to_string(tables.0[table].ext),
+            kind: ext,
+            entries: &tables.1[table]
+        }
+    })
+}
+
+fn lookup_entry(tables: &Tables, table: usize, id: usize) -> Result<(u32, TableEntry), DrsError> {
+    let ext = try!(tables.0.get(table).ok_or(DrsError::TableNotFound(table as _))).ext;
+
+    match tables.1[table].binary_search_by_key(&id, |&e| e.id as _) {
+        Ok(key) => Ok((ext, tables.1[table][key])),
+        Err(_) => Err(DrsError::EntryNotFound(id as _))
+    }
+}
+
+fn find_entry(tables: &Tables, id: usize) -> Result<(u32, TableEntry), DrsError> {
+    for table in 0..tables.0.len() {
+        if let Ok(found) = lookup_entry(tables, table, id) {
+            return Ok(found);
+        }
+    }
+
+    Err(DrsError::EntryNotFound(id as _))
+}
+
+const DRS_TABLE_INFO_SIZE: u32 = 12;
+const DRS_TABLE_ENTRY_SIZE: u32 = 12;
+
+/// Builds a DRS archive from files grouped into tables by their extension.
+///
+/// Tables are written in the order they were first added to, and entries
+/// within a table are sorted by id so that `DrsReader::read_file` can find
+/// them again.
+pub struct DrsWriter {
+    header: Header,
+    tables: Vec<(u32, Vec<(u32, Box<[u8]>)>)>
+}
+
+impl DrsWriter {
+    /// Creates an empty archive with the header used by Age of Kings.
+    pub fn new() -> DrsWriter {
+        DrsWriter::for_variant(DrsVariant::Tribe)
+    }
+
+    /// Creates an empty archive with the header used by the given game.
+    pub fn for_variant(variant: DrsVariant) -> DrsWriter {
+        match variant {
+            DrsVariant::StarWars => {
+                DrsWriter::with_header(DrsVariant::StarWars, b"Copyright (c) 2001 LucasArts Entertainment Company LLC\x1a", b"1.00", b"swbg")
+            },
+            _ => {
+                DrsWriter::with_header(DrsVariant::Tribe, b"Copyright (c) 1997 Ensemble Studios.\x1a", b"1.00", b"tribe")
+            }
+        }
+    }
+
+    /// Creates an empty archive with a custom header, using the copyright
+    /// length of `variant`. Fields longer than their slot in the header are
+    /// truncated, shorter ones are padded with zeroes.
+    pub fn with_header(variant: DrsVariant, copyright: &[u8], version: &[u8], ty: &[u8]) -> DrsWriter {
+        fn fill(dst: &mut [u8], src: &[u8]) {
+            let len = ::std::cmp::min(dst.len(), src.len());
+            dst[..len].copy_from_slice(&src[..len]);
+        }
+
+        let mut header = Header {
+            copyright: vec![0u8; variant.copyright_len()],
+            version: [0u8; 4],
+            ty: [0u8; 12],
+            tables: 0,
+            offset: 0
+        };
+
+        fill(&mut header.copyright, copyright);
+        fill(&mut header.version, version);
+        fill(&mut header.ty, ty);
+
+        DrsWriter {
+            header: header,
+            tables: Vec::new()
+        }
+    }
+
+    /// Copies the header, tables and entries of an existing archive, keeping
+    /// the order of tables and of the entries within them, even if their ids
+    /// are unsorted or duplicated.
+    ///
+    /// `write` stores entries back to back in table order right after the
+    /// entry tables, as the games' own archives do, so those are reproduced
+    /// byte for byte. Gaps or padding between entries in other archives are
+    /// dropped and the offsets recomputed.
+    pub fn from_reader<R: Read + Seek>(reader: &mut DrsReader<R>) -> Result<DrsWriter, DrsError> {
+        let mut writer = DrsWriter {
+            header: reader.header.clone(),
+            tables: Vec::new()
+        };
 
-            Ok(match (ext & 0xffffff00) {
-                0x62696e00 => DrsFile::Bin(data),
-                0x736c7000 => DrsFile::Slp(data),
-                0x77617600 => DrsFile::Wav(data),
-                _ => DrsFile::Unknown(ext, data)
-            })
-        } else {
-            Err(DrsError::EntryNotFound(id as _))
+        for table in 0..reader.tables.0.len() {
+            let ext = reader.tables.0[table].ext;
+            let table_entries = reader.tables.1[table].clone();
+
+            let mut entries = Vec::with_capacity(table_entries.len());
+            for entry in table_entries {
+                let data = try!(reader.read_entry(ext, entry)).into_data();
+
+                entries.push((entry.id, data));
+            }
+
+            writer.tables.push((ext, entries));
+        }
+
+        Ok(writer)
+    }
+
+    /// Adds a file to the table matching its type, creating the table if
+    /// needed. A file with an id that is already present replaces it.
+    pub fn add_file(&mut self, id: u32, file: DrsFile) {
+        let ext = file.ext().to_u32();
+
+        self.add_raw(ext, id, file.into_data());
+    }
+
+    /// Adds a file to the table with the given packed extension, e.g.
+    /// `0x736c7020` for `slp`.
+    pub fn add_raw(&mut self, ext: u32, id: u32, data: Box<[u8]>) {
+        let pos = match self.tables.iter().position(|t| t.0 == ext) {
+            Some(pos) => pos,
+            None => {
+                self.tables.push((ext, Vec::new()));
+                self.tables.len() - 1
+            }
+        };
+
+        let entries = &mut self.tables[pos].1;
+        match entries.binary_search_by_key(&id, |e| e.0) {
+            Ok(idx) => entries[idx].1 = data,
+            Err(idx) => entries.insert(idx, (id, data))
         }
     }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+        let entry_count = self.tables.iter().map(|t| t.1.len() as u32).sum::<u32>();
+        let data_offset = self.header.size()
+            + self.tables.len() as u32 * DRS_TABLE_INFO_SIZE
+            + entry_count * DRS_TABLE_ENTRY_SIZE;
+
+        try!(writer.write_all(&self.header.copyright));
+        try!(writer.write_all(&self.header.version));
+        try!(writer.write_all(&self.header.ty));
+        try!(writer.write_u32::<LittleEndian>(self.tables.len() as _));
+        try!(writer.write_u32::<LittleEndian>(data_offset));
+
+        let mut table_offset = self.header.size() + self.tables.len() as u32 * DRS_TABLE_INFO_SIZE;
+        for &(ext, ref entries) in self.tables.iter() {
+            try!(writer.write_u32::<LittleEndian>(ext));
+            try!(writer.write_u32::<LittleEndian>(table_offset));
+            try!(writer.write_u32::<LittleEndian>(entries.len() as _));
+
+            table_offset += entries.len() as u32 * DRS_TABLE_ENTRY_SIZE;
+        }
+
+        let mut offset = data_offset;
+        for &(_, ref entries) in self.tables.iter() {
+            for &(id, ref data) in entries.iter() {
+                try!(writer.write_u32::<LittleEndian>(id));
+                try!(writer.write_u32::<LittleEndian>(offset));
+                try!(writer.write_u32::<LittleEndian>(data.len() as _));
+
+                offset += data.len() as u32;
+            }
+        }
+
+        for &(_, ref entries) in self.tables.iter() {
+            for &(_, ref data) in entries.iter() {
+                try!(writer.write_all(data));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Turns a packed table extension such as `0x736c7020` into its name, `slp`.
+fn ext_to_string(ext: u32) -> String {
+    let mut bytes = [0u8; 4];
+    for i in 0..4 {
+        bytes[i] = (ext >> ((3 - i) * 8)) as u8;
+    }
+
+    String::from_utf8_lossy(&bytes).trim_right_matches(|c| c == ' ' || c == '\0').to_string()
 }
 
 fn read_drs_header<R: Read>(reader: &mut R) -> Result<Header, ::std::io::Error> {
-    let mut copyright: [u8; 40] = [0u8; 40];
+    fn is_version(version: &[u8; 4]) -> bool {
+        version[0].is_ascii_digit() && version[1] == b'.' && version[2].is_ascii_digit() && version[3].is_ascii_digit()
+    }
+
+    let mut copyright = vec![0u8; 40];
     let mut version: [u8; 4] = [0u8; 4];
     let mut ty: [u8; 12] = [0u8; 12];
 
     try!(reader.read_exact(&mut copyright));
     try!(reader.read_exact(&mut version));
+
+    // Star Wars: Galactic Battlegrounds has a 60 byte copyright, so what
+    // would be the version in other games is still part of the copyright.
+    if !is_version(&version) {
+        let mut rest = [0u8; 16];
+        try!(reader.read_exact(&mut rest));
+
+        copyright.extend_from_slice(&version);
+        copyright.extend_from_slice(&rest);
+        try!(reader.read_exact(&mut version));
+    }
+
     try!(reader.read_exact(&mut ty));
 
     let tables = try!(reader.read_u32::<LittleEndian>());
     let offset = try!(reader.read_u32::<LittleEndian>());
 
     Ok(Header {
-        copyright: unsafe { ::std::mem::transmute(copyright) },
+        copyright: copyright,
         version: version,
         ty: ty,
         tables: tables,
@@ -154,9 +978,21 @@ fn read_drs_header<R: Read>(reader: &mut R) -> Result<Header, ::std::io::Error>
     })
 }
 
-fn read_drs_table_info<R: Read + Seek>(header: Header, reader: &mut R) -> Result<Vec<TableInfo>, ::std::io::Error> {
+/// Number of 12 byte records starting at `offset` that fit in a stream of
+/// `len` bytes, at most `count`. The table info and entry tables are only
+/// read as far as the file goes, so truncated archives can still be opened
+/// and validated.
+fn records_before_eof(offset: u64, count: u32, len: u64) -> u32 {
+    ::std::cmp::min(count as u64, len.saturating_sub(offset) / 12) as u32
+}
+
+fn read_drs_table_info<R: Read + Seek>(header: &Header, reader: &mut R) -> Result<Vec<TableInfo>, ::std::io::Error> {
+    let pos = try!(reader.seek(SeekFrom::Current(0)));
+    let len = try!(reader.seek(SeekFrom::End(0)));
+    try!(reader.seek(SeekFrom::Start(pos)));
+
     let mut infos = vec![];
-    for x in 0..header.tables {
+    for _ in 0..records_before_eof(pos, header.tables, len) {
         let ext = try!(reader.read_u32::<LittleEndian>());
         let offset = try!(reader.read_u32::<LittleEndian>());
         let size = try!(reader.read_u32::<LittleEndian>());
@@ -172,13 +1008,14 @@ fn read_drs_table_info<R: Read + Seek>(header: Header, reader: &mut R) -> Result
 }
 
 fn read_drs_tables<R: Read + Seek>(table_infos: &Vec<TableInfo>, reader: &mut R) -> Result<Vec<Vec<TableEntry>>, ::std::io::Error> {
+    let len = try!(reader.seek(SeekFrom::End(0)));
     let mut table_entries = vec![];
 
     for info in table_infos {
         let mut entries = vec![];
-        reader.seek(SeekFrom::Start(info.offset as _));
+        try!(reader.seek(SeekFrom::Start(info.offset as _)));
 
-        for _ in 0..info.size {
+        for _ in 0..records_before_eof(info.offset as u64, info.size, len) {
             let id = try!(reader.read_u32::<LittleEndian>());
             let offset = try!(reader.read_u32::<LittleEndian>());
             let size = try!(reader.read_u32::<LittleEndian>());
@@ -195,3 +1032,163 @@ fn read_drs_tables<R: Read + Seek>(table_infos: &Vec<TableInfo>, reader: &mut R)
 
     Ok(table_entries)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn archive() -> Vec<u8> {
+        let mut writer = DrsWriter::new();
+        writer.add_raw(DrsExt::Slp.to_u32(), 2, vec![1, 2, 3].into_boxed_slice());
+        writer.add_raw(DrsExt::Slp.to_u32(), 1, vec![4; 17].into_boxed_slice());
+        writer.add_raw(DrsExt::Bin.to_u32(), 50000, vec![].into_boxed_slice());
+        writer.add_raw(0x61626364, 7, b"unknown".to_vec().into_boxed_slice());
+
+        let mut out = Vec::new();
+        writer.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn variants() {
+        for &(variant, copyright, ty) in &[
+            (DrsVariant::Tribe, "Copyright (c) 1997 Ensemble Studios.", "tribe"),
+            (DrsVariant::StarWars, "Copyright (c) 2001 LucasArts Entertainment Company LLC", "swbg")
+        ] {
+            let mut writer = DrsWriter::for_variant(variant);
+            writer.add_raw(DrsExt::Wav.to_u32(), 5, b"RIFF".to_vec().into_boxed_slice());
+            writer.add_raw(DrsExt::Slp.to_u32(), 9, vec![1, 2, 3].into_boxed_slice());
+
+            let mut data = Vec::new();
+            writer.write(&mut data).unwrap();
+            let len = variant.copyright_len();
+            assert_eq!(&data[len..len + 4], b"1.00");
+
+            let mut reader = DrsReader::new(Cursor::new(&data[..])).unwrap();
+            assert_eq!(reader.variant(), variant);
+            assert_eq!(reader.copyright(), copyright);
+            assert_eq!(reader.version(), "1.00");
+            assert_eq!(reader.archive_type(), ty);
+            assert!(reader.validate().unwrap().issues.is_empty());
+            assert_eq!(&reader.read_file_ext(DrsExt::Slp, 9).unwrap().into_data()[..], &[1, 2, 3]);
+
+            let archive = DrsArchive::new(&data[..]).unwrap();
+            assert_eq!(archive.variant(), variant);
+            assert_eq!(archive.get_ext(DrsExt::Wav, 5).unwrap(), b"RIFF");
+
+            let mut repacked = Vec::new();
+            DrsWriter::from_reader(&mut reader).unwrap().write(&mut repacked).unwrap();
+            assert_eq!(data, repacked);
+        }
+    }
+
+    /// Counts seeks relative to the end of the stream.
+    struct EndSeeks<'a>(Cursor<&'a [u8]>, usize);
+
+    impl<'a> Read for EndSeeks<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<'a> Seek for EndSeeks<'a> {
+        fn seek(&mut self, pos: SeekFrom) -> ::std::io::Result<u64> {
+            if let SeekFrom::End(_) = pos {
+                self.1 += 1;
+            }
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn stream_length_cached() {
+        let data = archive();
+        let mut reader = DrsReader::new(EndSeeks(Cursor::new(&data[..]), 0)).unwrap();
+        let seeks = reader.reader.1;
+
+        assert_eq!(&reader.read_file_ext(DrsExt::Slp, 2).unwrap().into_data()[..], &[1, 2, 3]);
+        assert_eq!(&reader.read_file_ext(DrsExt::Slp, 1).unwrap().into_data()[..], &[4; 17][..]);
+        reader.validate().unwrap();
+        assert_eq!(reader.reader.1, seeks);
+    }
+
+    #[test]
+    fn repack_round_trip() {
+        let original = archive();
+
+        let mut reader = DrsReader::new(Cursor::new(&original[..])).unwrap();
+        assert_eq!(reader.validate().unwrap().issues, vec![DrsIssue::UnknownExtension { table: 2, ext: 0x61626364 }]);
+        assert_eq!(&reader.read_file_ext(DrsExt::Slp, 1).unwrap().into_data()[..], &[4; 17][..]);
+        assert_eq!(reader.tables()[2].ext, "abcd");
+
+        let mut repacked = Vec::new();
+        DrsWriter::from_reader(&mut reader).unwrap().write(&mut repacked).unwrap();
+
+        assert_eq!(original, repacked);
+    }
+
+    #[test]
+    fn repack_unsorted_ids() {
+        // Unsorted and duplicated ids of the two slp entries. The slp table
+        // is first, its offset follows its extension in the table info after
+        // the 64 byte header.
+        for &(first, second, ref issue) in [
+            (2, 1, DrsIssue::UnsortedIds { table: 0, id: 1, previous: 2 }),
+            (1, 1, DrsIssue::DuplicateId { table: 0, id: 1 })
+        ].iter() {
+            let mut original = archive();
+            let table = u32_at(&original, 68) as usize;
+            original[table] = first;
+            original[table + 12] = second;
+
+            let mut reader = DrsReader::new(Cursor::new(&original[..])).unwrap();
+            assert!(reader.validate().unwrap().issues.contains(issue));
+
+            let mut repacked = Vec::new();
+            DrsWriter::from_reader(&mut reader).unwrap().write(&mut repacked).unwrap();
+
+            assert_eq!(original, repacked);
+        }
+    }
+
+    #[test]
+    fn truncated_tables() {
+        let data = archive();
+        let table = u32_at(&data, 68) as usize;
+
+        // Cut the archive in the middle of the second slp entry.
+        let truncated = &data[..table + 18];
+        let mut reader = DrsReader::new(Cursor::new(truncated)).unwrap();
+        let issues = reader.validate().unwrap().issues;
+        assert!(issues.contains(&DrsIssue::TablePastEof { table: 0 }));
+        assert!(issues.contains(&DrsIssue::TablePastEof { table: 1 }));
+        assert_eq!(reader.tables()[0].len(), 1);
+
+        // Cut it in the middle of the table info.
+        let archive = DrsArchive::new(&data[..64 + 20]).unwrap();
+        assert!(archive.validate().issues.contains(&DrsIssue::TableInfoPastEof { declared: 3, found: 1 }));
+    }
+
+    #[test]
+    fn entry_past_eof() {
+        let mut data = archive();
+
+        // Make the first slp entry claim far more bytes than the archive has.
+        let table = u32_at(&data, 68) as usize;
+        data[table + 8..table + 12].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+
+        let mut reader = DrsReader::new(Cursor::new(&data[..])).unwrap();
+        match reader.read_file_ext(DrsExt::Slp, 1) {
+            Err(DrsError::Io(ref err)) if err.kind() == ErrorKind::UnexpectedEof => {},
+            other => panic!("unexpected result {:?}", other.map(|_| ()))
+        }
+
+        let archive = DrsArchive::new(&data[..]).unwrap();
+        assert!(archive.get_ext(DrsExt::Slp, 1).is_err());
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        data[pos] as u32 | (data[pos + 1] as u32) << 8 | (data[pos + 2] as u32) << 16 | (data[pos + 3] as u32) << 24
+    }
+}
@@ -1,5 +1,8 @@
 
 pub mod drs;
 pub mod slp;
+pub mod smx;
+pub mod smp;
 pub mod dat;
 pub mod pal;
+pub mod sniff;
@@ -0,0 +1,262 @@
+use std::io::{Write, Read, Seek, ErrorKind};
+use byteorder::{BigEndian, WriteBytesExt};
+use image::RgbaImage;
+use image::imageops::flip_horizontal;
+use deflate;
+use gif;
+use png;
+use png::HasParameters;
+
+use format::pal::Palette;
+use super::{SlpReader, SlpError, SlpRasterOptions, SlpBlendMode};
+
+/// How the frames of an animated graphic are laid out in its SLP file,
+/// matching the graphic entries of the game data.
+///
+/// Frames are stored direction by direction, `frames_per_direction` each,
+/// starting with the unit facing south and turning clockwise. With
+/// `mirrored` set only `directions / 2 + 1` directions are stored, from
+/// south to north, and the remaining ones are drawn as horizontal mirrors
+/// of their western counterparts.
+#[derive(Debug, Copy, Clone)]
+pub struct SlpAnimation {
+    /// Number of facings, including mirrored ones, usually 1, 8 or 16.
+    pub directions: usize,
+    pub frames_per_direction: usize,
+    pub mirrored: bool,
+    /// Duration of each frame in milliseconds.
+    pub frame_duration: u32
+}
+
+impl SlpAnimation {
+    /// Number of directions stored in the file.
+    pub fn stored_directions(&self) -> usize {
+        if self.mirrored && self.directions > 1 {
+            self.directions / 2 + 1
+        } else {
+            self.directions
+        }
+    }
+
+    /// Index in the file of frame `frame` of direction `direction`, and
+    /// whether it must be mirrored.
+    ///
+    /// Fails with `SlpError::AnimationFrame` if `direction` or `frame` is
+    /// out of range.
+    pub fn frame_index(&self, direction: usize, frame: usize) -> Result<(usize, bool), SlpError> {
+        if direction >= self.directions || frame >= self.frames_per_direction {
+            return Err(SlpError::AnimationFrame(direction, frame));
+        }
+
+        if direction < self.stored_directions() {
+            Ok((direction * self.frames_per_direction + frame, false))
+        } else {
+            Ok(((self.directions - direction) * self.frames_per_direction + frame, true))
+        }
+    }
+
+    /// Rasterizes the frames of one direction onto a common canvas, aligned
+    /// on their hotspots. Frames are drawn over `background`, or onto a
+    /// transparent canvas if it is `None`.
+    ///
+    /// Fails with `SlpError::AnimationFrame` if `direction` is out of
+    /// range.
+    pub fn render<R: Read + Seek>(&self, reader: &mut SlpReader<R>, direction: usize, pal: &Palette, options: &SlpRasterOptions, background: Option<[u8; 4]>) -> Result<SlpAnimationFrames, SlpError> {
+        let mut images = Vec::with_capacity(self.frames_per_direction);
+
+        for frame in 0..self.frames_per_direction {
+            let (index, mirror) = try!(self.frame_index(direction, frame));
+            let image = try!(reader.read_image(index, pal, options));
+            let (x, y) = try!(reader.frames().get(index).ok_or(SlpError::FrameNotFound(index))).hotspot();
+
+            if mirror {
+                images.push(((image.width() as i32 - x, y), flip_horizontal(&image)));
+            } else {
+                images.push(((x, y), image));
+            }
+        }
+
+        // Room needed left, above, right and below the hotspot.
+        let left = images.iter().map(|&((x, _), _)| x).max().unwrap_or(0);
+        let top = images.iter().map(|&((_, y), _)| y).max().unwrap_or(0);
+        let right = images.iter().map(|&((x, _), ref image)| image.width() as i32 - x).max().unwrap_or(0);
+        let bottom = images.iter().map(|&((_, y), ref image)| image.height() as i32 - y).max().unwrap_or(0);
+
+        let (width, height) = ((left + right) as u32, (top + bottom) as u32);
+        let frames = images.into_iter().map(|((x, y), image)| {
+            let mut canvas = RgbaImage::new(width, height);
+            if let Some(background) = background {
+                for px in canvas.pixels_mut() {
+                    px.data = background;
+                }
+            }
+
+            let (dx, dy) = ((left - x) as u32, (top - y) as u32);
+            for (x, y, px) in image.enumerate_pixels() {
+                SlpBlendMode::Alpha.blend(&mut canvas.get_pixel_mut(dx + x, dy + y).data, px.data);
+            }
+
+            canvas
+        }).collect();
+
+        Ok(SlpAnimationFrames {
+            frames: frames,
+            hotspot: (left, top),
+            frame_duration: self.frame_duration
+        })
+    }
+}
+
+/// The frames of one direction of an animation, all of the same size.
+pub struct SlpAnimationFrames {
+    pub frames: Vec<RgbaImage>,
+    /// Position of the hotspot on the canvas.
+    pub hotspot: (i32, i32),
+    /// Duration of each frame in milliseconds.
+    pub frame_duration: u32
+}
+
+impl SlpAnimationFrames {
+    fn size(&self) -> (u32, u32) {
+        self.frames.first().map_or((0, 0), |frame| frame.dimensions())
+    }
+
+    /// Writes a looping GIF. GIF has no partial transparency, so shadows
+    /// and outlines become opaque unless the frames were rendered over a
+    /// background, and colors are quantized per frame.
+    pub fn write_gif<W: Write>(&self, writer: W) -> Result<(), ::std::io::Error> {
+        let (width, height) = self.size();
+        if width > 0xffff || height > 0xffff {
+            return Err(::std::io::Error::new(ErrorKind::InvalidInput, "frames too large for GIF"));
+        }
+
+        // GIF delays are in hundredths of a second.
+        let delay = (self.frame_duration as u64 + 5) / 10;
+        if delay > 0xffff {
+            return Err(::std::io::Error::new(ErrorKind::InvalidInput, "frame duration too long for GIF"));
+        }
+
+        let mut encoder = try!(gif::Encoder::new(writer, width as u16, height as u16, &[]));
+        try!(encoder.write_extension(gif::ExtensionData::Repetitions(gif::Repeat::Infinite)));
+
+        for image in self.frames.iter() {
+            let mut pixels = image.clone().into_raw();
+            let mut frame = gif::Frame::from_rgba(width as u16, height as u16, &mut pixels);
+            frame.delay = delay as u16;
+            frame.dispose = gif::DisposalMethod::Background;
+
+            try!(encoder.write_frame(&frame));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a looping APNG.
+    pub fn write_apng<W: Write>(&self, writer: W) -> Result<(), ::std::io::Error> {
+        let (width, height) = self.size();
+
+        // Delays are a 16-bit number of milliseconds here.
+        if self.frame_duration > 0xffff {
+            return Err(::std::io::Error::new(ErrorKind::InvalidInput, "frame duration too long for APNG"));
+        }
+
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+        let mut writer = try!(encoder.write_header());
+
+        let mut actl = Vec::with_capacity(8);
+        try!(actl.write_u32::<BigEndian>(self.frames.len() as u32));
+        try!(actl.write_u32::<BigEndian>(0));
+        try!(writer.write_chunk(png::chunk::acTL, &actl));
+
+        // fcTL and fdAT chunks share a sequence number.
+        let mut sequence = 0;
+
+        for (i, image) in self.frames.iter().enumerate() {
+            let mut fctl = Vec::with_capacity(26);
+            try!(fctl.write_u32::<BigEndian>(sequence));
+            try!(fctl.write_u32::<BigEndian>(width));
+            try!(fctl.write_u32::<BigEndian>(height));
+            try!(fctl.write_u32::<BigEndian>(0));
+            try!(fctl.write_u32::<BigEndian>(0));
+            try!(fctl.write_u16::<BigEndian>(self.frame_duration as u16));
+            try!(fctl.write_u16::<BigEndian>(1000));
+            // Clear to transparent after each frame, and draw without
+            // blending.
+            fctl.push(1);
+            fctl.push(0);
+            try!(writer.write_chunk(png::chunk::fcTL, &fctl));
+            sequence += 1;
+
+            let data = deflate::deflate_bytes_zlib(&filter_sub(image));
+            if i == 0 {
+                try!(writer.write_chunk(png::chunk::IDAT, &data));
+            } else {
+                let mut fdat = Vec::with_capacity(data.len() + 4);
+                try!(fdat.write_u32::<BigEndian>(sequence));
+                fdat.extend_from_slice(&data);
+                try!(writer.write_chunk(png::chunk::fdAT, &fdat));
+                sequence += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Prefixes each row with the PNG "sub" filter and applies it.
+fn filter_sub(image: &RgbaImage) -> Vec<u8> {
+    let row_len = image.width() as usize * 4;
+    let mut out = Vec::with_capacity((row_len + 1) * image.height() as usize);
+
+    for row in image.chunks(row_len) {
+        out.push(1);
+        for i in 0..row.len() {
+            let prev = if i >= 4 { row[i - 4] } else { 0 };
+            out.push(row[i].wrapping_sub(prev));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use format::slp::SlpError;
+    use super::*;
+
+    fn frames(frame_duration: u32) -> SlpAnimationFrames {
+        SlpAnimationFrames {
+            frames: vec![RgbaImage::new(1, 1)],
+            hotspot: (0, 0),
+            frame_duration: frame_duration
+        }
+    }
+
+    #[test]
+    fn frame_index() {
+        let anim = SlpAnimation { directions: 8, frames_per_direction: 3, mirrored: true, frame_duration: 100 };
+
+        assert_eq!(anim.stored_directions(), 5);
+        assert_eq!(anim.frame_index(0, 2).unwrap(), (2, false));
+        assert_eq!(anim.frame_index(4, 0).unwrap(), (12, false));
+        assert_eq!(anim.frame_index(5, 1).unwrap(), (10, true));
+        assert_eq!(anim.frame_index(7, 0).unwrap(), (3, true));
+
+        for &(direction, frame) in &[(8, 0), (9, 0), (0, 3)] {
+            match anim.frame_index(direction, frame) {
+                Err(SlpError::AnimationFrame(d, f)) => assert_eq!((d, f), (direction, frame)),
+                other => panic!("{:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn frame_duration_limits() {
+        assert!(frames(0xffff).write_apng(Vec::new()).is_ok());
+        assert_eq!(frames(0x10000).write_apng(Vec::new()).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        assert!(frames(655354).write_gif(Vec::new()).is_ok());
+        assert_eq!(frames(655355).write_gif(Vec::new()).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::{Write, Read, Seek};
+use std::fs::File;
+use std::path::Path;
+use image::RgbaImage;
+
+use format::pal::Palette;
+use super::{SlpReader, SlpError, SlpRasterOptions, SlpDefaultRasterizer, SlpBlendMode, SlpTarget, MAX_SIZE};
+
+/// Options controlling how frames are packed by `SlpAtlas::build`.
+#[derive(Debug, Copy, Clone)]
+pub struct SlpAtlasOptions {
+    /// Maximum size of a page. Frames that don't fit, padding included,
+    /// get a page of their own, sized to fit them.
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Transparent pixels around each frame, to keep texture filtering
+    /// from bleeding into neighbouring frames.
+    pub padding: u32,
+    /// Round page sizes up to powers of two, but no further than the
+    /// maximum size.
+    pub power_of_two: bool
+}
+
+impl Default for SlpAtlasOptions {
+    fn default() -> Self {
+        SlpAtlasOptions {
+            max_width: 2048,
+            max_height: 2048,
+            padding: 1,
+            power_of_two: false
+        }
+    }
+}
+
+/// Where a frame was placed in an atlas.
+#[derive(Debug, Copy, Clone)]
+pub struct SlpAtlasFrame {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (i32, i32)
+}
+
+/// All frames of an SLP file packed into one or more pages.
+pub struct SlpAtlas {
+    pub pages: Vec<RgbaImage>,
+    /// Placement of each frame, in frame order.
+    pub frames: Vec<SlpAtlasFrame>
+}
+
+impl SlpAtlas {
+    /// Packs and rasterizes all main frames of `reader`. Frames are placed
+    /// on shelves, tallest first.
+    pub fn build<R: Read + Seek>(reader: &mut SlpReader<R>, pal: &Palette, raster: &SlpRasterOptions, options: &SlpAtlasOptions) -> Result<SlpAtlas, SlpError> {
+        let padding = options.padding;
+        if let Some(entry) = reader.frames().iter().find(|entry| entry.width() as u32 > MAX_SIZE || entry.height() as u32 > MAX_SIZE) {
+            return Err(SlpError::BadDimensions(entry.width(), entry.height()));
+        }
+
+        let mut frames: Vec<SlpAtlasFrame> = reader.frames().iter().map(|entry| {
+            SlpAtlasFrame {
+                page: 0,
+                x: 0,
+                y: 0,
+                width: ::std::cmp::max(entry.width(), 0) as u32,
+                height: ::std::cmp::max(entry.height(), 0) as u32,
+                hotspot: entry.hotspot()
+            }
+        }).collect();
+
+        let mut order: Vec<usize> = (0..frames.len()).collect();
+        order.sort_by_key(|&i| ::std::cmp::Reverse(frames[i].height));
+
+        // Size of each page so far. `full` is set once the last page holds
+        // an oversized frame, so that nothing else goes on it.
+        let mut sizes: Vec<(u32, u32)> = Vec::new();
+        let (mut x, mut y, mut shelf, mut full) = (padding, padding, 0, false);
+
+        for &i in order.iter() {
+            let (width, height) = (frames[i].width, frames[i].height);
+            let oversized = width + 2 * padding > options.max_width || height + 2 * padding > options.max_height;
+
+            if !sizes.is_empty() && x + width + padding > options.max_width && x > padding {
+                x = padding;
+                y += shelf + padding;
+                shelf = 0;
+            }
+            if sizes.is_empty() || full || oversized || (y + height + padding > options.max_height && y > padding) {
+                sizes.push((0, 0));
+                x = padding;
+                y = padding;
+                shelf = 0;
+                full = oversized;
+            }
+
+            let page = sizes.len() - 1;
+            frames[i].page = page;
+            frames[i].x = x;
+            frames[i].y = y;
+
+            let size = &mut sizes[page];
+            size.0 = ::std::cmp::max(size.0, x + width + padding);
+            size.1 = ::std::cmp::max(size.1, y + height + padding);
+
+            x += width + padding;
+            shelf = ::std::cmp::max(shelf, height);
+        }
+
+        // Oversized pages are already larger than the maximum, and keep
+        // their size.
+        let round = |size: u32, max: u32| {
+            match size.checked_next_power_of_two() {
+                Some(rounded) if options.power_of_two && size <= max => ::std::cmp::min(rounded, max),
+                _ => size
+            }
+        };
+        let mut pages: Vec<RgbaImage> = sizes.iter().map(|&(width, height)| {
+            RgbaImage::new(round(width, options.max_width), round(height, options.max_height))
+        }).collect();
+
+        for (i, frame) in frames.iter().enumerate() {
+            if reader.is_delta(i) {
+                let image = try!(reader.read_image(i, pal, raster));
+                let page = &mut pages[frame.page];
+                let pixels: &[u8] = &image;
+                let row_len = image.width() as usize * 4;
+                let x = frame.x as usize * 4;
+
+                for y in 0..image.height() as usize {
+                    page.row_mut(frame.y as usize + y)[x..x + row_len].copy_from_slice(&pixels[y * row_len..(y + 1) * row_len]);
+                }
+            } else {
+                let obj = try!(reader.read_shape(i));
+                try!(SlpDefaultRasterizer::draw(&mut pages[frame.page], &obj, pal, raster, frame.x as _, frame.y as _, SlpBlendMode::Replace));
+            }
+        }
+
+        Ok(SlpAtlas {
+            pages: pages,
+            frames: frames
+        })
+    }
+
+    /// Writes a JSON manifest listing the pages, as named by `page_name`,
+    /// and the rectangle and hotspot of each frame:
+    ///
+    /// ```text
+    /// {
+    ///   "pages": ["units-0.png"],
+    ///   "frames": [
+    ///     {"page": 0, "x": 1, "y": 1, "width": 52, "height": 60, "hotspot_x": 26, "hotspot_y": 48}
+    ///   ]
+    /// }
+    /// ```
+    pub fn write_manifest<W: Write, F: Fn(usize) -> String>(&self, writer: &mut W, page_name: F) -> Result<(), ::std::io::Error> {
+        try!(write!(writer, "{{\n  \"pages\": ["));
+        for i in 0..self.pages.len() {
+            if i > 0 {
+                try!(write!(writer, ", "));
+            }
+            try!(write_json_string(writer, &page_name(i)));
+        }
+        try!(write!(writer, "],\n  \"frames\": [\n"));
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            try!(write!(writer, "    {{\"page\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"hotspot_x\": {}, \"hotspot_y\": {}}}{}\n",
+                frame.page, frame.x, frame.y, frame.width, frame.height, frame.hotspot.0, frame.hotspot.1,
+                if i + 1 < self.frames.len() { "," } else { "" }));
+        }
+
+        write!(writer, "  ]\n}}\n")
+    }
+
+    /// Saves the pages as `<name>-<page>.png` and the manifest as
+    /// `<name>.json` in `dir`.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<(), ::std::io::Error> {
+        let page_name = |i: usize| format!("{}-{}.png", name, i);
+
+        for (i, page) in self.pages.iter().enumerate() {
+            try!(page.save(dir.join(page_name(i))));
+        }
+
+        let mut file = try!(File::create(dir.join(format!("{}.json", name))));
+        self.write_manifest(&mut file, page_name)
+    }
+}
+
+fn write_json_string<W: Write>(writer: &mut W, s: &str) -> Result<(), ::std::io::Error> {
+    try!(write!(writer, "\""));
+    for c in s.chars() {
+        match c {
+            '"' => try!(write!(writer, "\\\"")),
+            '\\' => try!(write!(writer, "\\\\")),
+            c if (c as u32) < 0x20 => try!(write!(writer, "\\u{:04x}", c as u32)),
+            c => try!(write!(writer, "{}", c))
+        }
+    }
+    write!(writer, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use format::pal::Palette;
+    use format::slp::{SlpReader, SlpWriter, SlpFrame, SlpPixel, SlpRasterOptions};
+    use super::*;
+
+    fn atlas(sizes: &[(u32, u32)], options: &SlpAtlasOptions) -> SlpAtlas {
+        let mut writer = SlpWriter::new();
+        for &(width, height) in sizes {
+            writer.add_frame(SlpFrame {
+                width: width,
+                height: height,
+                hotspot: (0, 0),
+                pixels: vec![SlpPixel::Color(1); (width * height) as usize].into_boxed_slice()
+            });
+        }
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+
+        let pal = Palette { colors: vec![(0xff, 0, 0); 256].into_boxed_slice() };
+        let mut reader = SlpReader::new(Cursor::new(data)).unwrap();
+        SlpAtlas::build(&mut reader, &pal, &SlpRasterOptions::default(), options).unwrap()
+    }
+
+    #[test]
+    fn oversized_frames() {
+        let options = SlpAtlasOptions { max_width: 8, max_height: 8, padding: 1, power_of_two: false };
+        let atlas = atlas(&[(2, 2), (12, 3), (3, 3), (2, 10)], &options);
+
+        // Tallest first: the 2x10 and 12x3 frames each get a page, and the
+        // others share one.
+        let pages: Vec<usize> = atlas.frames.iter().map(|frame| frame.page).collect();
+        assert_eq!(pages, vec![2, 1, 2, 0]);
+        assert_eq!(atlas.pages[0].dimensions(), (4, 12));
+        assert_eq!(atlas.pages[1].dimensions(), (14, 5));
+        assert_eq!(atlas.pages[2].dimensions(), (8, 5));
+    }
+
+    #[test]
+    fn power_of_two_within_max() {
+        let options = SlpAtlasOptions { max_width: 24, max_height: 24, padding: 1, power_of_two: true };
+        let atlas = atlas(&[(18, 3), (4, 4), (30, 2)], &options);
+
+        let sizes: Vec<(u32, u32)> = atlas.pages.iter().map(|page| page.dimensions()).collect();
+        assert_eq!(sizes, vec![(24, 16), (32, 4)]);
+    }
+}
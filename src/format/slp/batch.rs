@@ -0,0 +1,164 @@
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use image::RgbaImage;
+
+use format::pal::Palette;
+use task::JobSystem;
+use super::{SlpReader, SlpError, SlpRasterOptions, SlpFastRasterizer, SlpColorTable};
+
+/// A frame rasterized by `rasterize_batch`.
+pub struct SlpBatchFrame {
+    pub hotspot: (i32, i32),
+    pub image: RgbaImage
+}
+
+/// Decodes and rasterizes every frame of each SLP file in `files` on the
+/// job system's workers, one task per frame, using `SlpFastRasterizer`.
+///
+/// Returns the frames of each file in order, or the first error met while
+/// reading it. A file whose tasks panicked fails with `SlpError::Panicked`.
+pub fn rasterize_batch(jobs: &JobSystem, files: Vec<Vec<u8>>, pal: &Palette, options: &SlpRasterOptions) -> Result<Vec<Result<Vec<SlpBatchFrame>, SlpError>>, SlpError> {
+    let table = Arc::new(try!(SlpColorTable::new(pal, options)));
+
+    // One slot per file, filled with a slot per frame once the file's
+    // tables are read. Slots still empty after waiting belong to tasks
+    // that panicked.
+    let results: Arc<Vec<Mutex<Option<FileResult>>>> = Arc::new(
+        files.iter().map(|_| Mutex::new(None)).collect()
+    );
+
+    let files: Vec<Arc<[u8]>> = files.into_iter().map(Arc::from).collect();
+
+    let root = {
+        let results = results.clone();
+
+        jobs.spawn(move |worker| {
+            for (i, data) in files.into_iter().enumerate() {
+                let results = results.clone();
+                let table = table.clone();
+
+                worker.spawn(move |worker| {
+                    let reader = match SlpReader::new(Cursor::new(data.clone())) {
+                        Ok(reader) => reader,
+                        Err(err) => {
+                            *results[i].lock().unwrap() = Some(Err(err));
+                            return;
+                        }
+                    };
+
+                    *results[i].lock().unwrap() = Some(Ok((0..reader.len()).map(|_| None).collect()));
+
+                    for frame in 0..reader.len() {
+                        let results = results.clone();
+                        let table = table.clone();
+                        let mut reader = reader.with_stream(Cursor::new(data.clone()));
+
+                        worker.spawn(move |_| {
+                            let res = rasterize_frame(&mut reader, frame, &table);
+
+                            let mut file = results[i].lock().unwrap();
+                            match (res, file.as_mut()) {
+                                (Ok(res), Some(&mut Ok(ref mut frames))) => frames[frame] = Some(res),
+                                (Err(err), Some(&mut Ok(_))) => *file = Some(Err(err)),
+                                _ => {}
+                            }
+                        });
+                    }
+                });
+            }
+        })
+    };
+
+    jobs.wait(&root);
+
+    let results = match Arc::try_unwrap(results) {
+        Ok(results) => results,
+        Err(_) => unreachable!("tasks still hold the results after finishing")
+    };
+
+    Ok(results.into_iter().map(|file| {
+        match file.into_inner().unwrap() {
+            Some(Ok(frames)) => frames.into_iter().collect::<Option<Vec<_>>>().ok_or(SlpError::Panicked),
+            Some(Err(err)) => Err(err),
+            None => Err(SlpError::Panicked)
+        }
+    }).collect())
+}
+
+type FileResult = Result<Vec<Option<SlpBatchFrame>>, SlpError>;
+
+fn rasterize_frame(reader: &mut SlpReader<Cursor<Arc<[u8]>>>, frame: usize, table: &SlpColorTable) -> Result<SlpBatchFrame, SlpError> {
+    let obj = try!(reader.read_shape(frame));
+
+    let (width, height) = (obj.width as u32, obj.height as u32);
+    let mut pixels = vec![0u8; try!(obj.buffer_len(4))];
+
+    // Delta frames are drawn over the frames before them, back to their
+    // keyframe.
+    for index in reader.keyframe(frame)..frame {
+        let base = try!(reader.read_shape(index));
+        if (base.width, base.height) != (obj.width, obj.height) {
+            return Err(SlpError::BadDimensions(base.width, base.height));
+        }
+
+        try!(SlpFastRasterizer::rasterize(&mut pixels, width as usize * 4, &base, table));
+    }
+    try!(SlpFastRasterizer::rasterize(&mut pixels, width as usize * 4, &obj, table));
+
+    Ok(SlpBatchFrame {
+        hotspot: obj.hotspot(),
+        image: RgbaImage::from_raw(width, height, pixels).unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use format::pal::Palette;
+    use format::slp::{SlpWriter, SlpFrame, SlpPixel, SlpError, SlpRasterOptions};
+    use task::JobSystem;
+    use super::*;
+
+    fn file(sizes: &[(u32, u32)]) -> Vec<u8> {
+        let mut writer = SlpWriter::new();
+        for (i, &(width, height)) in sizes.iter().enumerate() {
+            writer.add_frame(SlpFrame {
+                width: width,
+                height: height,
+                hotspot: (i as i32, 0),
+                pixels: vec![SlpPixel::Color(i as u8 + 1); (width * height) as usize].into_boxed_slice()
+            });
+        }
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn frames_in_order() {
+        let pal = Palette {
+            colors: (0..256).map(|i| (i as u8, 0, 0)).collect::<Vec<_>>().into_boxed_slice()
+        };
+        let sizes = [(3, 2), (0, 0), (1, 5), (4, 4)];
+        let files = vec![file(&sizes), b"not an slp".to_vec(), file(&[])];
+
+        for &threads in &[0, 3] {
+            let jobs = JobSystem::new(threads);
+            let mut results = rasterize_batch(&jobs, files.clone(), &pal, &SlpRasterOptions::default()).unwrap().into_iter();
+
+            let frames = results.next().unwrap().unwrap();
+            assert_eq!(frames.len(), sizes.len());
+            for (i, frame) in frames.iter().enumerate() {
+                assert_eq!(frame.hotspot, (i as i32, 0));
+                assert_eq!(frame.image.dimensions(), sizes[i]);
+                assert!(frame.image.pixels().all(|px| px.data == [i as u8 + 1, 0, 0, 0xff]));
+            }
+
+            match results.next().unwrap() {
+                Err(SlpError::Panicked) | Ok(_) => panic!("expected a decoding error"),
+                Err(_) => {}
+            }
+            assert_eq!(results.next().unwrap().unwrap().len(), 0);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::Write;
+
+use super::{SlpObject, SlpError, read_command};
+
+/// Operation of a decoded SLP command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpOp {
+    ColorList,
+    Skip,
+    PlayerColorList,
+    Fill,
+    PlayerColorFill,
+    Shadow,
+    /// Extended commands that draw nothing: draw direction hints and shadow
+    /// table selection.
+    Hint,
+    PlayerOutline,
+    BlackOutline,
+    EndOfRow
+}
+
+impl SlpOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            SlpOp::ColorList => "color_list",
+            SlpOp::Skip => "skip",
+            SlpOp::PlayerColorList => "player_color_list",
+            SlpOp::Fill => "fill",
+            SlpOp::PlayerColorFill => "player_color_fill",
+            SlpOp::Shadow => "shadow",
+            SlpOp::Hint => "hint",
+            SlpOp::PlayerOutline => "player_outline",
+            SlpOp::BlackOutline => "black_outline",
+            SlpOp::EndOfRow => "end_of_row"
+        }
+    }
+}
+
+/// A single command of a frame's command stream.
+#[derive(Debug, Clone)]
+pub struct SlpCommand {
+    pub row: usize,
+    /// Offset of the command byte in the file.
+    pub offset: usize,
+    /// The command byte itself.
+    pub opcode: u8,
+    pub op: SlpOp,
+    /// Number of pixels drawn or skipped.
+    pub len: usize,
+    /// Pixel data following the command, if any.
+    pub data: Vec<u8>
+}
+
+/// Iterator over the commands of a frame, row by row, created by
+/// `SlpObject::commands`.
+pub struct SlpCommands<'a> {
+    obj: &'a SlpObject,
+    row: usize,
+    /// Commands of the current row, the position of the next one and
+    /// their file offset.
+    reader: Option<(&'a [u8], usize, usize)>
+}
+
+impl SlpObject {
+    /// Decodes the commands of each row without rasterizing them, starting
+    /// each row at its offset from the row offset table. Rows marked as
+    /// fully transparent are skipped.
+    pub fn commands(&self) -> SlpCommands {
+        SlpCommands {
+            obj: self,
+            row: 0,
+            reader: None
+        }
+    }
+}
+
+impl<'a> Iterator for SlpCommands<'a> {
+    type Item = Result<SlpCommand, SlpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.reader.is_none() {
+            if self.row >= self.obj.height as usize {
+                return None;
+            }
+
+            if self.obj.is_empty_row(self.row) {
+                self.row += 1;
+            } else {
+                let (commands, offset) = self.obj.row(self.row);
+                self.reader = Some((commands, 0, offset));
+            }
+        }
+
+        let res = {
+            let (data, ref mut pos, row_offset) = *self.reader.as_mut().unwrap();
+            next_command(data, *pos, row_offset, self.row, self.obj.kind.bytes_per_pixel()).map(|(command, next)| {
+                *pos = next;
+                command
+            })
+        };
+
+        match res {
+            Ok(ref command) if command.op == SlpOp::EndOfRow => {
+                self.reader = None;
+                self.row += 1;
+            },
+            Ok(_) => {},
+            Err(_) => {
+                // Stop at the first error rather than misreading the rest.
+                self.reader = None;
+                self.row = self.obj.height as usize;
+            }
+        }
+
+        Some(res)
+    }
+}
+
+fn next_command(data: &[u8], pos: usize, row_offset: usize, row: usize, bpp: usize) -> Result<(SlpCommand, usize), SlpError> {
+    let command = try!(read_command(data, pos, row_offset, bpp));
+
+    Ok((SlpCommand {
+        row: row,
+        offset: row_offset + pos,
+        opcode: data[pos],
+        op: command.op,
+        len: command.len,
+        data: command.data.to_vec()
+    }, command.next))
+}
+
+/// Writes a text listing of a frame's commands, one line per command
+/// grouped by row, in the form:
+///
+/// ```text
+/// row 0: left 1, right 0
+///   0x00000060  0x08  color_list              2  c8 c8
+///   0x00000063  0x16  player_color_list       1  03
+///   0x00000065  0x1b  shadow                  1
+///   0x00000066  0x0f  end_of_row              0
+/// row 1: empty
+/// ```
+pub fn write_disassembly<W: Write>(writer: &mut W, obj: &SlpObject) -> Result<(), SlpError> {
+    try!(writeln!(writer, "frame {}x{}, hotspot {},{}, {:?}", obj.width, obj.height, obj.hotspot.0, obj.hotspot.1, obj.kind));
+
+    let mut row = None;
+    for command in obj.commands() {
+        let command = try!(command);
+
+        if row != Some(command.row) {
+            // Note the transparent rows skipped since the previous one.
+            for y in row.map_or(0, |y| y + 1)..command.row {
+                try!(writeln!(writer, "row {}: empty", y));
+            }
+
+            let (left, right) = obj.skips[command.row];
+            try!(writeln!(writer, "row {}: left {}, right {}", command.row, left, right));
+            row = Some(command.row);
+        }
+
+        try!(write!(writer, "  0x{:08x}  0x{:02x}  {:<20} {:>4}", command.offset, command.opcode, command.op.name(), command.len));
+        if !command.data.is_empty() {
+            try!(write!(writer, " "));
+            for byte in command.data.iter() {
+                try!(write!(writer, " {:02x}", byte));
+            }
+        }
+        try!(writeln!(writer, ""));
+    }
+
+    for y in row.map_or(0, |y| y + 1)..obj.height as usize {
+        try!(writeln!(writer, "row {}: empty", y));
+    }
+
+    Ok(())
+}
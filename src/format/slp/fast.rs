@@ -0,0 +1,219 @@
+use format::pal::Palette;
+use super::{SlpObject, SlpError, SlpRasterOptions, SlpFrameKind, SlpSpanKind, SHADOW_COLOR, BLACK_OUTLINE_COLOR, PLAYER_OUTLINE_ALPHA};
+use super::pixel_color;
+
+/// RGBA colors of a palette and of the player colors chosen by
+/// `SlpRasterOptions`, built once and shared by any number of
+/// `SlpFastRasterizer` calls.
+pub struct SlpColorTable {
+    colors: Box<[[u8; 4]]>,
+    /// Number of entries of `colors` present in the palette.
+    len: usize,
+    player: Box<[[u8; 4]]>,
+    player_len: usize,
+    player_base: usize,
+    player_outline: [u8; 4],
+    /// Kept for frames that are not 8-bit, which take the slow path.
+    palette: Palette
+}
+
+impl SlpColorTable {
+    pub fn new(pal: &Palette, options: &SlpRasterOptions) -> Result<SlpColorTable, SlpError> {
+        let rgba = |col: &(u8, u8, u8)| [col.0, col.1, col.2, 0xff];
+
+        let mut colors = vec![[0u8; 4]; 256];
+        for (dst, col) in colors.iter_mut().zip(pal.colors.iter()) {
+            *dst = rgba(col);
+        }
+
+        let mut player = vec![[0u8; 4]; 256];
+        for (dst, col) in player.iter_mut().zip(pal.colors.iter().skip(options.player_base)) {
+            *dst = rgba(col);
+        }
+
+        let mut player_outline = match pal.colors.get(options.player_outline) {
+            Some(col) => rgba(col),
+            None => return Err(SlpError::PaletteIndex(options.player_outline))
+        };
+        player_outline[3] = PLAYER_OUTLINE_ALPHA;
+
+        Ok(SlpColorTable {
+            colors: colors.into_boxed_slice(),
+            len: pal.colors.len(),
+            player: player.into_boxed_slice(),
+            player_len: pal.colors.len().saturating_sub(options.player_base),
+            player_base: options.player_base,
+            player_outline: player_outline,
+            palette: Palette {
+                colors: pal.colors.clone()
+            }
+        })
+    }
+
+    #[inline(always)]
+    fn color(&self, idx: u8) -> Result<[u8; 4], SlpError> {
+        if (idx as usize) < self.len {
+            Ok(self.colors[idx as usize])
+        } else {
+            Err(SlpError::PaletteIndex(idx as usize))
+        }
+    }
+
+    #[inline(always)]
+    fn player(&self, idx: u8) -> Result<[u8; 4], SlpError> {
+        if (idx as usize) < self.player_len {
+            Ok(self.player[idx as usize])
+        } else {
+            Err(SlpError::PaletteIndex(self.player_base + idx as usize))
+        }
+    }
+}
+
+/// Rasterizes to 32-bit RGBA like `SlpDefaultRasterizer`, but draws spans
+/// straight into a slice, taking colors from an `SlpColorTable` and
+/// writing runs with slice fills.
+///
+/// With the `simd` feature, fills use SSE2 stores on x86-64.
+pub struct SlpFastRasterizer {
+}
+
+impl SlpFastRasterizer {
+    /// Rasterizes into `out`, RGBA pixels with rows `stride` bytes apart.
+    /// Pixels the frame does not draw are left untouched.
+    ///
+    /// Fails with `SlpError::BufferTooSmall` if `out` is too small to hold
+    /// the frame or `stride` is shorter than a row.
+    pub fn rasterize(out: &mut [u8], stride: usize, obj: &SlpObject, table: &SlpColorTable) -> Result<(), SlpError> {
+        try!(obj.buffer_len(4));
+        let width = obj.width as usize;
+        let height = obj.height as usize;
+        let bpp = obj.kind.bytes_per_pixel();
+
+        let needed = if height == 0 {
+            Some(0)
+        } else {
+            (height - 1).checked_mul(stride).and_then(|len| len.checked_add(width * 4))
+        };
+        match needed {
+            Some(needed) if stride >= width * 4 && out.len() >= needed => {},
+            needed => return Err(SlpError::BufferTooSmall(needed.unwrap_or(usize::max_value())))
+        }
+
+        for y in 0..height {
+            let row = &mut out[y * stride..y * stride + width * 4];
+
+            for span in obj.row_spans(y) {
+                let span = try!(span);
+                let pixels = &mut row[span.x * 4..(span.x + span.len) * 4];
+
+                match span.kind {
+                    SlpSpanKind::Skip => {},
+                    SlpSpanKind::Colors(data) => {
+                        if obj.kind == SlpFrameKind::Palette {
+                            for (dst, &idx) in pixels.chunks_mut(4).zip(data.iter()) {
+                                dst.copy_from_slice(&try!(table.color(idx)));
+                            }
+                        } else {
+                            for (dst, px) in pixels.chunks_mut(4).zip(data.chunks(bpp)) {
+                                dst.copy_from_slice(&try!(pixel_color(px, obj.kind, &table.palette)));
+                            }
+                        }
+                    },
+                    SlpSpanKind::Fill(px) => {
+                        let col = if obj.kind == SlpFrameKind::Palette {
+                            try!(table.color(px[0]))
+                        } else {
+                            try!(pixel_color(px, obj.kind, &table.palette))
+                        };
+                        fill(pixels, col);
+                    },
+                    SlpSpanKind::PlayerColors(data) => {
+                        for (dst, &idx) in pixels.chunks_mut(4).zip(data.iter()) {
+                            dst.copy_from_slice(&try!(table.player(idx)));
+                        }
+                    },
+                    SlpSpanKind::PlayerFill(idx) => fill(pixels, try!(table.player(idx))),
+                    SlpSpanKind::Shadow => fill(pixels, SHADOW_COLOR),
+                    SlpSpanKind::PlayerOutline => fill(pixels, table.player_outline),
+                    SlpSpanKind::BlackOutline => fill(pixels, BLACK_OUTLINE_COLOR)
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+#[inline(always)]
+fn fill(dst: &mut [u8], col: [u8; 4]) {
+    for px in dst.chunks_mut(4) {
+        px.copy_from_slice(&col);
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[inline(always)]
+fn fill(dst: &mut [u8], col: [u8; 4]) {
+    use std::arch::x86_64::{__m128i, _mm_set1_epi32, _mm_storeu_si128};
+
+    let mut chunks = dst.chunks_exact_mut(16);
+
+    // SSE2 is part of the x86-64 baseline, so this needs no detection.
+    unsafe {
+        let v = _mm_set1_epi32(i32::from_ne_bytes(col));
+        for chunk in &mut chunks {
+            _mm_storeu_si128(chunk.as_mut_ptr() as *mut __m128i, v);
+        }
+    }
+
+    for px in chunks.into_remainder().chunks_mut(4) {
+        px.copy_from_slice(&col);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use format::pal::Palette;
+    use format::slp::{SlpReader, SlpWriter, SlpFrame, SlpPixel, SlpError, SlpRasterOptions};
+    use super::*;
+
+    #[test]
+    fn buffer_checks() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(SlpFrame {
+            width: 3,
+            height: 2,
+            hotspot: (0, 0),
+            pixels: vec![SlpPixel::Color(1); 6].into_boxed_slice()
+        });
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+
+        let mut obj = SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap();
+        let pal = Palette { colors: vec![(1, 2, 3); 256].into_boxed_slice() };
+        let table = SlpColorTable::new(&pal, &SlpRasterOptions::default()).unwrap();
+
+        // The last row needs no padding after it.
+        let mut out = vec![0u8; 16 + 12];
+        SlpFastRasterizer::rasterize(&mut out, 16, &obj, &table).unwrap();
+        assert_eq!(&out[16..20], &[1, 2, 3, 0xff]);
+
+        let mut short = vec![0u8; 16 + 11];
+        match SlpFastRasterizer::rasterize(&mut short, 16, &obj, &table) {
+            Err(SlpError::BufferTooSmall(28)) => {},
+            other => panic!("{:?}", other)
+        }
+        match SlpFastRasterizer::rasterize(&mut out, 8, &obj, &table) {
+            Err(SlpError::BufferTooSmall(_)) => {},
+            other => panic!("{:?}", other)
+        }
+
+        obj.width = -1;
+        match SlpFastRasterizer::rasterize(&mut out, 16, &obj, &table) {
+            Err(SlpError::BadDimensions(-1, 2)) => {},
+            other => panic!("{:?}", other)
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::io::{Write, ErrorKind};
+use png;
+use png::HasParameters;
+
+use format::pal::Palette;
+use super::{SlpObject, SlpRasterOptions, SlpFrameKind, SlpSpanKind, SlpError};
+
+/// Rasterizes frames to 8-bit palette indices instead of RGBA, keeping the
+/// original indices so frames can be re-encoded or drawn with a different
+/// palette.
+///
+/// Player color pixels are offset into the player's band like in
+/// `SlpDefaultRasterizer`. Pixels outside the frame, shadows and outlines
+/// have no palette index and are set to the transparent index; use
+/// `SlpLayeredRasterizer` to get their masks.
+pub struct SlpIndexedRasterizer {
+}
+
+impl SlpIndexedRasterizer {
+    /// Rasterizes an 8-bit frame into one palette index per pixel.
+    ///
+    /// Fails with `SlpError::FrameKind` for frames without palette indices,
+    /// such as 32-bit frames and shadow or damage mask frames, whose colors
+    /// would be lost. Fails with `SlpError::TransparentIndex` if the frame
+    /// draws a pixel with the `transparent` index, as it could not be told
+    /// apart from transparency, and with `SlpError::PaletteIndex` if a
+    /// player color lies past index 255.
+    pub fn rasterize(obj: &SlpObject, options: &SlpRasterOptions, transparent: u8) -> Result<Box<[u8]>, SlpError> {
+        let width = obj.width as usize;
+        let height = obj.height as usize;
+        if obj.kind != SlpFrameKind::Palette {
+            return Err(SlpError::FrameKind(obj.kind));
+        }
+
+        let check = |idx: usize| -> Result<u8, SlpError> {
+            if idx > 0xff {
+                Err(SlpError::PaletteIndex(idx))
+            } else if idx == transparent as usize {
+                Err(SlpError::TransparentIndex(transparent))
+            } else {
+                Ok(idx as u8)
+            }
+        };
+
+        let mut out = vec![transparent; try!(obj.buffer_len(1))].into_boxed_slice();
+
+        for y in 0..height {
+            let row = y * width;
+
+            for span in obj.row_spans(y) {
+                let span = try!(span);
+                let pixels = &mut out[row + span.x..row + span.x + span.len];
+
+                match span.kind {
+                    SlpSpanKind::Colors(data) => {
+                        for (p, &idx) in pixels.iter_mut().zip(data.iter()) {
+                            *p = try!(check(idx as usize));
+                        }
+                    },
+                    SlpSpanKind::Fill(px) => {
+                        let idx = try!(check(px[0] as usize));
+                        for p in pixels {
+                            *p = idx;
+                        }
+                    },
+                    SlpSpanKind::PlayerColors(data) => {
+                        for (p, &idx) in pixels.iter_mut().zip(data.iter()) {
+                            *p = try!(check(options.player_base + idx as usize));
+                        }
+                    },
+                    SlpSpanKind::PlayerFill(idx) => {
+                        let idx = try!(check(options.player_base + idx as usize));
+                        for p in pixels {
+                            *p = idx;
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Encodes 8-bit palette indices as an indexed PNG carrying `pal`, with
+/// `transparent` marked as fully transparent if given. The transparent
+/// index must be one of the palette's colors.
+pub fn write_indexed_png<W: Write>(writer: W, data: &[u8], width: u32, height: u32, pal: &Palette, transparent: Option<u8>) -> Result<(), ::std::io::Error> {
+    let colors = ::std::cmp::min(pal.colors.len(), 256);
+    if transparent.map_or(false, |idx| idx as usize >= colors) {
+        return Err(::std::io::Error::new(ErrorKind::InvalidInput, "transparent index is past the end of the palette"));
+    }
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+    let mut writer = try!(encoder.write_header());
+
+    let mut plte = Vec::with_capacity(pal.colors.len() * 3);
+    for &(r, g, b) in pal.colors.iter().take(256) {
+        plte.extend_from_slice(&[r, g, b]);
+    }
+    try!(writer.write_chunk(png::chunk::PLTE, &plte));
+
+    if let Some(transparent) = transparent {
+        let mut trns = vec![0xffu8; transparent as usize + 1];
+        trns[transparent as usize] = 0;
+        try!(writer.write_chunk(png::chunk::tRNS, &trns));
+    }
+
+    try!(writer.write_image_data(data));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use format::pal::Palette;
+    use format::slp::{SlpReader, SlpWriter, SlpFrame, SlpPixel, SlpRasterOptions, SlpError};
+    use super::*;
+
+    fn frame(pixels: Vec<SlpPixel>) -> Vec<u8> {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(SlpFrame {
+            width: pixels.len() as u32,
+            height: 1,
+            hotspot: (0, 0),
+            pixels: pixels.into_boxed_slice()
+        });
+
+        let mut out = Vec::new();
+        writer.write(&mut out).unwrap();
+        out
+    }
+
+    fn rasterize(data: Vec<u8>, options: &SlpRasterOptions, transparent: u8) -> Result<Box<[u8]>, SlpError> {
+        let obj = SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap();
+        SlpIndexedRasterizer::rasterize(&obj, options, transparent)
+    }
+
+    #[test]
+    fn indices() {
+        let data = frame(vec![SlpPixel::Transparent, SlpPixel::Color(5), SlpPixel::Player(2), SlpPixel::Shadow]);
+        let out = rasterize(data, &SlpRasterOptions::for_player(2).unwrap(), 0xff).unwrap();
+
+        assert_eq!(&out[..], &[0xff, 5, 26, 0xff]);
+    }
+
+    #[test]
+    fn transparent_index_used() {
+        let data = frame(vec![SlpPixel::Color(0), SlpPixel::Color(1)]);
+
+        match rasterize(data.clone(), &SlpRasterOptions::default(), 0) {
+            Err(SlpError::TransparentIndex(0)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(rasterize(data, &SlpRasterOptions::default(), 0xff).is_ok());
+    }
+
+    #[test]
+    fn player_past_palette() {
+        let data = frame(vec![SlpPixel::Player(7)]);
+        let options = SlpRasterOptions {
+            player_base: 250,
+            player_outline: 0
+        };
+
+        match rasterize(data, &options, 0) {
+            Err(SlpError::PaletteIndex(257)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unindexed_frames() {
+        let data = frame(vec![SlpPixel::Color(5), SlpPixel::Player(2)]);
+        let mut obj = SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap();
+
+        for &kind in &[SlpFrameKind::Bgra32, SlpFrameKind::Shadow, SlpFrameKind::DamageMask] {
+            obj.kind = kind;
+            match SlpIndexedRasterizer::rasterize(&obj, &SlpRasterOptions::default(), 0) {
+                Err(SlpError::FrameKind(k)) => assert_eq!(k, kind),
+                other => panic!("unexpected result {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_past_palette() {
+        let pal = Palette {
+            colors: vec![(0, 0, 0); 16].into_boxed_slice()
+        };
+
+        assert!(write_indexed_png(Vec::new(), &[0; 4], 2, 2, &pal, Some(15)).is_ok());
+        assert!(write_indexed_png(Vec::new(), &[0; 4], 2, 2, &pal, Some(16)).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use format::pal::Palette;
+use super::{SlpObject, SlpRasterOptions, SlpError, SlpSpanKind, pixel_color, palette_color};
+
+/// Outline mask value of player color outline pixels.
+pub const OUTLINE_PLAYER: u8 = 1;
+/// Outline mask value of black outline pixels.
+pub const OUTLINE_BLACK: u8 = 2;
+
+/// A frame split into separate planes, for renderers that composite player
+/// colors, shadows and outlines themselves.
+///
+/// All planes are `width * height` pixels in row-major order. Masks hold
+/// `0xff` where they apply and `0` elsewhere.
+#[derive(Debug)]
+pub struct SlpLayers {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA of regular pixels. Player color, shadow and outline pixels are
+    /// left transparent.
+    pub color: Box<[u8]>,
+    /// Index into the player's palette band of each player color pixel.
+    pub player: Box<[u8]>,
+    pub player_mask: Box<[u8]>,
+    /// Shadow intensity, always `0xff` for SLP shadows.
+    pub shadow: Box<[u8]>,
+    /// `OUTLINE_PLAYER` or `OUTLINE_BLACK` for outline pixels, `0`
+    /// elsewhere.
+    pub outline: Box<[u8]>,
+    /// Pixels inside the row margins of the frame's outline table.
+    pub transparency: Box<[u8]>
+}
+
+/// Rasterizes frames into `SlpLayers` rather than a single image.
+pub struct SlpLayeredRasterizer {
+}
+
+impl SlpLayeredRasterizer {
+    pub fn rasterize(obj: &SlpObject, pal: &Palette) -> Result<SlpLayers, SlpError> {
+        let width = obj.width as usize;
+        let height = obj.height as usize;
+        let len = try!(obj.buffer_len(4)) / 4;
+        let bpp = obj.kind.bytes_per_pixel();
+
+        let mut layers = SlpLayers {
+            width: width,
+            height: height,
+            color: vec![0u8; len * 4].into_boxed_slice(),
+            player: vec![0u8; len].into_boxed_slice(),
+            player_mask: vec![0u8; len].into_boxed_slice(),
+            shadow: vec![0u8; len].into_boxed_slice(),
+            outline: vec![0u8; len].into_boxed_slice(),
+            transparency: vec![0u8; len].into_boxed_slice()
+        };
+
+        for y in 0..height {
+            if obj.is_empty_row(y) {
+                continue;
+            }
+
+            let (left, right) = obj.skips[y];
+            let row = y * width;
+            let end = width.saturating_sub(right as usize);
+            for x in (left as usize)..end {
+                layers.transparency[row + x] = 0xff;
+            }
+
+            for span in obj.row_spans(y) {
+                let span = try!(span);
+                let start = row + span.x;
+                let pixels = start..start + span.len;
+
+                match span.kind {
+                    SlpSpanKind::Skip => {},
+                    SlpSpanKind::Colors(data) => {
+                        for (pos, px) in pixels.zip(data.chunks(bpp)) {
+                            layers.set_color(pos, try!(pixel_color(px, obj.kind, pal)));
+                        }
+                    },
+                    SlpSpanKind::Fill(px) => {
+                        let col = try!(pixel_color(px, obj.kind, pal));
+                        for pos in pixels {
+                            layers.set_color(pos, col);
+                        }
+                    },
+                    SlpSpanKind::PlayerColors(data) => {
+                        for (pos, &idx) in pixels.zip(data.iter()) {
+                            layers.set_player(pos, idx);
+                        }
+                    },
+                    SlpSpanKind::PlayerFill(idx) => {
+                        for pos in pixels {
+                            layers.set_player(pos, idx);
+                        }
+                    },
+                    SlpSpanKind::Shadow => {
+                        for pos in pixels {
+                            layers.shadow[pos] = 0xff;
+                        }
+                    },
+                    SlpSpanKind::PlayerOutline => {
+                        for pos in pixels {
+                            layers.outline[pos] = OUTLINE_PLAYER;
+                        }
+                    },
+                    SlpSpanKind::BlackOutline => {
+                        for pos in pixels {
+                            layers.outline[pos] = OUTLINE_BLACK;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(layers)
+    }
+}
+
+impl SlpLayers {
+    /// Combines the color and player planes into a single RGBA image, using
+    /// the player colors from `options`.
+    pub fn flatten(&self, pal: &Palette, options: &SlpRasterOptions) -> Result<Box<[u8]>, SlpError> {
+        let mut out = self.color.clone();
+
+        for i in 0..self.player_mask.len() {
+            if self.player_mask[i] != 0 {
+                let col = try!(palette_color(pal, options.player_base + self.player[i] as usize));
+                out[i * 4..i * 4 + 4].copy_from_slice(&col);
+            }
+        }
+
+        Ok(out)
+    }
+
+    #[inline(always)]
+    fn set_color(&mut self, pos: usize, col: [u8; 4]) {
+        self.color[pos * 4..pos * 4 + 4].copy_from_slice(&col);
+    }
+
+    #[inline(always)]
+    fn set_player(&mut self, pos: usize, idx: u8) {
+        self.player[pos] = idx;
+        self.player_mask[pos] = 0xff;
+    }
+}
@@ -0,0 +1,1087 @@
+use std::io::{Write, Read, Seek, SeekFrom, ErrorKind};
+use std::error;
+use std::fmt;
+use std::iter;
+use std::sync::Arc;
+use byteorder::{LittleEndian, BigEndian, ReadBytesExt};
+
+use format::pal::Palette;
+use format::dat::PlayerColor;
+
+pub mod layers;
+pub mod indexed;
+pub mod writer;
+pub mod disasm;
+pub mod target;
+pub mod atlas;
+pub mod anim;
+pub mod batch;
+pub mod fast;
+pub mod spans;
+
+pub use self::layers::{SlpLayers, SlpLayeredRasterizer};
+pub use self::indexed::{SlpIndexedRasterizer, write_indexed_png};
+pub use self::writer::{SlpWriter, SlpFrame, SlpPixel};
+pub use self::disasm::{SlpCommand, SlpOp, write_disassembly};
+pub use self::target::{SlpTarget, SlpSliceTarget, SlpBlendMode};
+pub use self::atlas::{SlpAtlas, SlpAtlasOptions, SlpAtlasFrame};
+pub use self::anim::{SlpAnimation, SlpAnimationFrames};
+pub use self::batch::{SlpBatchFrame, rasterize_batch};
+pub use self::fast::{SlpFastRasterizer, SlpColorTable};
+pub use self::spans::{SlpSpan, SlpSpanKind, SlpRowSpans};
+
+#[derive(Debug)]
+pub enum SlpError {
+    Io(::std::io::Error),
+    /// The file ended in the middle of a header, table or command.
+    Truncated,
+    /// A frame table or row offset points outside the file.
+    BadOffset(u32),
+    FrameNotFound(usize),
+    /// A frame has a negative width or height, or one larger than an SLP
+    /// file can hold.
+    BadDimensions(i32, i32),
+    /// The commands of a row draw past the width of the frame.
+    RowOverflow(usize),
+    /// A pixel refers to a color past the end of the palette.
+    PaletteIndex(usize),
+    /// An unknown command byte, with its offset in the file.
+    UnknownCommand(u8, usize),
+    /// A pixel uses the index `SlpIndexedRasterizer` reserves for
+    /// transparency.
+    TransparentIndex(u8),
+    /// A direction and frame outside the layout of an `SlpAnimation`.
+    AnimationFrame(usize, usize),
+    /// A task decoding the frame panicked, see `rasterize_batch`.
+    Panicked,
+    /// An output buffer or its stride is too small for the frame, with
+    /// the number of bytes needed, see `SlpFastRasterizer::rasterize`.
+    BufferTooSmall(usize),
+    /// The frame holds a kind of pixels the operation can't handle, such
+    /// as 32-bit colors in `SlpIndexedRasterizer`.
+    FrameKind(SlpFrameKind)
+}
+
+impl From<::std::io::Error> for SlpError {
+    fn from(err: ::std::io::Error) -> Self {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            SlpError::Truncated
+        } else {
+            SlpError::Io(err)
+        }
+    }
+}
+
+impl fmt::Display for SlpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SlpError::Io(ref err) => write!(f, "IO error: {}", err),
+            SlpError::Truncated => write!(f, "SLP data is truncated"),
+            SlpError::BadOffset(offset) => write!(f, "Offset {} is outside the file", offset),
+            SlpError::FrameNotFound(index) => write!(f, "Frame {} not found", index),
+            SlpError::BadDimensions(width, height) => write!(f, "Invalid frame size {}x{}", width, height),
+            SlpError::RowOverflow(row) => write!(f, "Row {} is wider than the frame", row),
+            SlpError::PaletteIndex(idx) => write!(f, "Palette index {} out of range", idx),
+            SlpError::UnknownCommand(command, offset) => write!(f, "Unknown command 0x{:02X} at offset {}", command, offset),
+            SlpError::TransparentIndex(idx) => write!(f, "Transparent index {} is used by a pixel", idx),
+            SlpError::AnimationFrame(direction, frame) => write!(f, "Frame {} of direction {} is outside the animation", frame, direction),
+            SlpError::Panicked => write!(f, "Decoding panicked"),
+            SlpError::BufferTooSmall(len) => write!(f, "Output buffer too small, {} bytes needed", len),
+            SlpError::FrameKind(kind) => write!(f, "Unsupported frame kind {:?}", kind)
+        }
+    }
+}
+
+impl error::Error for SlpError {
+    fn description(&self) -> &str {
+        match *self {
+            SlpError::Io(ref err) => err.description(),
+            SlpError::Truncated => "SLP data is truncated",
+            SlpError::BadOffset(_) => "SLP offset out of range",
+            SlpError::FrameNotFound(_) => "SLP frame not found",
+            SlpError::BadDimensions(..) => "invalid SLP frame size",
+            SlpError::RowOverflow(_) => "SLP row wider than frame",
+            SlpError::PaletteIndex(_) => "palette index out of range",
+            SlpError::UnknownCommand(..) => "unknown SLP command",
+            SlpError::TransparentIndex(_) => "transparent index used by a pixel",
+            SlpError::AnimationFrame(..) => "frame outside the animation",
+            SlpError::Panicked => "decoding panicked",
+            SlpError::BufferTooSmall(_) => "output buffer too small",
+            SlpError::FrameKind(_) => "unsupported frame kind"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SlpError::Io(ref err) => err.cause(),
+            _ => None
+        }
+    }
+}
+
+/// Version of an SLP file, detected from its header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpVersion {
+    /// `2.0N`, used by Age of Empires and Age of Kings.
+    V2,
+    /// `3.0`, which shares the layout of `2.0N`.
+    V3,
+    /// `4.0X` from the Definitive Editions, with 32-bit frames and
+    /// separate shadow frames.
+    V4_0,
+    /// `4.1X` from the Definitive Editions, with 32-bit frames and
+    /// separate damage mask frames.
+    V4_1,
+    Unknown([u8; 4])
+}
+
+impl SlpVersion {
+    fn from_bytes(version: [u8; 4]) -> SlpVersion {
+        match &version {
+            b"2.0N" => SlpVersion::V2,
+            b"3.0\0" => SlpVersion::V3,
+            b"4.0X" => SlpVersion::V4_0,
+            b"4.1X" => SlpVersion::V4_1,
+            _ => SlpVersion::Unknown(version)
+        }
+    }
+
+    fn is_v4(self) -> bool {
+        self == SlpVersion::V4_0 || self == SlpVersion::V4_1
+    }
+}
+
+/// How the pixels of a frame are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpFrameKind {
+    /// 8-bit palette indices.
+    Palette,
+    /// 32-bit BGRA colors, used by main frames in `4.x` files.
+    Bgra32,
+    /// 8-bit shadow intensities, used by secondary frames in `4.0X` files.
+    Shadow,
+    /// 8-bit damage mask values, used by secondary frames in `4.1X` files.
+    DamageMask
+}
+
+impl SlpFrameKind {
+    /// Number of bytes per pixel in color list and fill commands.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            SlpFrameKind::Bgra32 => 4,
+            _ => 1
+        }
+    }
+}
+
+/// Largest width or height of a frame. Row margins are 16-bit with
+/// `0x8000` marking fully transparent rows, which bounds the width, and the
+/// games have no frames anywhere near as tall.
+const MAX_SIZE: u32 = 0x7fff;
+
+/// Frames in `4.x` files with this property set hold 32-bit pixels.
+const PROPERTY_32BIT: u32 = 0x07;
+
+/// Frames in `4.x` files with this property set are delta frames, see
+/// `SlpObject::is_delta`.
+const PROPERTY_DELTA: u32 = 0x08;
+
+#[derive(Debug)]
+pub struct SlpHeader {
+    version: [u8; 4],
+    count: u32,
+    comment: [u8; 24],
+    /// Id of the palette used by 8-bit frames and offset of the main and
+    /// secondary frame tables, only present in `4.x` files.
+    palette: u32,
+    offset_main: u32,
+    offset_secondary: u32
+}
+
+/// Header of a frame from the frame table, available without decoding
+/// the frame, see `SlpReader::frames`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SlpEntry {
+    data_offset: u32,
+    outline_offset: u32,
+    palette_offset: u32,
+    properties: u32,
+    width: i32,
+    height: i32,
+    hotspot_x: i32,
+    hotspot_y: i32
+}
+
+impl SlpEntry {
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Position of the frame's anchor point, relative to its top left
+    /// corner. Renderers draw the frame at the unit's position minus the
+    /// hotspot.
+    pub fn hotspot(&self) -> (i32, i32) {
+        (self.hotspot_x, self.hotspot_y)
+    }
+
+    /// Frame property flags. In `4.x` files `0x07` marks 32-bit frames and
+    /// `0x08` delta frames.
+    pub fn properties(&self) -> u32 {
+        self.properties
+    }
+
+    /// Offset of the frame's palette, unused by the games.
+    pub fn palette_offset(&self) -> u32 {
+        self.palette_offset
+    }
+
+    /// File offset of the row offset table.
+    pub fn data_offset(&self) -> u32 {
+        self.data_offset
+    }
+
+    /// File offset of the row margin table.
+    pub fn outline_offset(&self) -> u32 {
+        self.outline_offset
+    }
+}
+
+pub trait SlpRasterizer {
+    /// Rasterizes with the default options, drawing player colors for
+    /// player 1.
+    fn rasterize<W: Write + Seek>(writer: &mut W, obj: &SlpObject, pal: &Palette) -> Result<(), SlpError> {
+        Self::rasterize_with(writer, obj, pal, &SlpRasterOptions::default())
+    }
+
+    fn rasterize_with<W: Write + Seek>(writer: &mut W, obj: &SlpObject, pal: &Palette, options: &SlpRasterOptions) -> Result<(), SlpError>;
+}
+
+/// Options controlling how an `SlpRasterizer` draws a frame.
+#[derive(Debug, Copy, Clone)]
+pub struct SlpRasterOptions {
+    /// Palette index of the first color in the player's band, which player
+    /// color commands are offset by.
+    pub player_base: usize,
+    /// Palette index used for player color outlines.
+    pub player_outline: usize
+}
+
+impl SlpRasterOptions {
+    /// Options for drawing as player `player` (1-8), assuming the player
+    /// color layout of the Age of Kings palette where each player has a
+    /// band of 8 colors starting at index 16. `None` for other players.
+    pub fn for_player(player: u8) -> Option<SlpRasterOptions> {
+        if player < 1 || player > 8 {
+            return None;
+        }
+
+        let base = PLAYER_BASE + (player - 1) as usize * PLAYER_BAND_SIZE;
+
+        Some(SlpRasterOptions {
+            player_base: base,
+            player_outline: base
+        })
+    }
+
+    /// Options for drawing with the player colors from the game data.
+    pub fn from_player_color(color: &PlayerColor) -> SlpRasterOptions {
+        SlpRasterOptions {
+            player_base: color.palette_base() as _,
+            player_outline: color.outline_color() as _
+        }
+    }
+}
+
+impl Default for SlpRasterOptions {
+    fn default() -> Self {
+        SlpRasterOptions {
+            player_base: PLAYER_BASE,
+            player_outline: PLAYER_BASE
+        }
+    }
+}
+
+/// Color of shadow pixels drawn by `SlpDefaultRasterizer`.
+pub const SHADOW_COLOR: [u8; 4] = [0, 0, 0, 0x80];
+
+/// Alpha of player color outline pixels drawn by `SlpDefaultRasterizer`,
+/// which are shown in place of a unit hidden behind a building.
+pub const PLAYER_OUTLINE_ALPHA: u8 = 0xc0;
+
+/// Color of black outline pixels drawn by `SlpDefaultRasterizer`.
+pub const BLACK_OUTLINE_COLOR: [u8; 4] = [0, 0, 0, 0xc0];
+
+const PLAYER_BASE: usize = 16;
+const PLAYER_BAND_SIZE: usize = 8;
+
+/// Rasterizes to 32-bit RGBA.
+///
+/// Player color pixels use the player colors from the options, shadows are
+/// drawn as translucent black and the two outline classes as translucent player
+/// color and black respectively, see `SHADOW_COLOR`,
+/// `PLAYER_OUTLINE_ALPHA` and `BLACK_OUTLINE_COLOR`.
+pub struct SlpDefaultRasterizer {
+}
+
+impl SlpRasterizer for SlpDefaultRasterizer {
+    fn rasterize_with<W: Write + Seek>(writer: &mut W, obj: &SlpObject, pal: &Palette, options: &SlpRasterOptions) -> Result<(), SlpError> {
+        let colors = try!(SpanColors::new(pal, obj.kind, options));
+        let width = obj.width as usize;
+
+        for y in 0..obj.height as usize {
+            let mut x = 0;
+
+            for span in obj.row_spans(y) {
+                let span = try!(span);
+
+                try!(writer.seek(SeekFrom::Current((span.x - x) as i64 * 4)));
+                if span.kind == SlpSpanKind::Skip {
+                    try!(writer.seek(SeekFrom::Current(span.len as i64 * 4)));
+                } else {
+                    try!(colors.draw(&span, |_, col| {
+                        try!(writer.write_all(&col));
+                        Ok(())
+                    }));
+                }
+
+                x = span.x + span.len;
+            }
+
+            try!(writer.seek(SeekFrom::Current((width - x) as i64 * 4)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Colors `SlpDefaultRasterizer` draws spans with.
+struct SpanColors<'a> {
+    pal: &'a Palette,
+    kind: SlpFrameKind,
+    player_base: usize,
+    player_outline: [u8; 4]
+}
+
+impl<'a> SpanColors<'a> {
+    fn new(pal: &'a Palette, kind: SlpFrameKind, options: &SlpRasterOptions) -> Result<SpanColors<'a>, SlpError> {
+        let mut player_outline = try!(palette_color(pal, options.player_outline));
+        player_outline[3] = PLAYER_OUTLINE_ALPHA;
+
+        Ok(SpanColors {
+            pal: pal,
+            kind: kind,
+            player_base: options.player_base,
+            player_outline: player_outline
+        })
+    }
+
+    /// Calls `put` with the column and color of each pixel of `span`, left
+    /// to right. `Skip` spans have none.
+    #[inline(always)]
+    fn draw<F: FnMut(usize, [u8; 4]) -> Result<(), SlpError>>(&self, span: &SlpSpan, mut put: F) -> Result<(), SlpError> {
+        let bpp = self.kind.bytes_per_pixel();
+        let pixels = span.x..span.x + span.len;
+
+        let col = match span.kind {
+            SlpSpanKind::Skip => return Ok(()),
+            SlpSpanKind::Colors(data) => {
+                for (x, px) in pixels.zip(data.chunks(bpp)) {
+                    try!(put(x, try!(pixel_color(px, self.kind, self.pal))));
+                }
+                return Ok(());
+            },
+            SlpSpanKind::PlayerColors(data) => {
+                for (x, &idx) in pixels.zip(data.iter()) {
+                    try!(put(x, try!(palette_color(self.pal, self.player_base + idx as usize))));
+                }
+                return Ok(());
+            },
+            SlpSpanKind::Fill(px) => try!(pixel_color(px, self.kind, self.pal)),
+            SlpSpanKind::PlayerFill(idx) => try!(palette_color(self.pal, self.player_base + idx as usize)),
+            SlpSpanKind::Shadow => SHADOW_COLOR,
+            SlpSpanKind::PlayerOutline => self.player_outline,
+            SlpSpanKind::BlackOutline => BLACK_OUTLINE_COLOR
+        };
+
+        for x in pixels {
+            try!(put(x, col));
+        }
+
+        Ok(())
+    }
+}
+
+/// A command decoded by `read_command`.
+struct Command<'a> {
+    op: SlpOp,
+    /// Number of pixels drawn or skipped.
+    len: usize,
+    /// Pixel data following the command.
+    data: &'a [u8],
+    /// Position of the next command.
+    next: usize
+}
+
+/// Decodes the command at `pos` in `data`, the commands of a frame with
+/// `bpp` bytes per pixel starting at file offset `offset`.
+///
+/// This is the only place commands are parsed; rasterizers work on the
+/// spans of `SlpObject::row_spans` instead.
+#[inline(always)]
+fn read_command(data: &[u8], pos: usize, offset: usize, bpp: usize) -> Result<Command, SlpError> {
+    let inst = try!(read_byte(data, pos));
+    let mut next = pos + 1;
+
+    // Operation, pixel count and number of data bytes that follow.
+    let (op, len, bytes) = match inst & 0x0f {
+        // Color list, 6 bit length.
+        0 | 4 | 8 | 0x0c => (SlpOp::ColorList, (inst >> 2) as usize, (inst >> 2) as usize * bpp),
+        // Skip, 6 bit length.
+        1 | 5 | 9 | 0x0d => (SlpOp::Skip, (inst >> 2) as usize, 0),
+        // Color list, 12 bit length.
+        2 => {
+            let len = try!(read_long_length(inst, data, &mut next));
+            (SlpOp::ColorList, len, len * bpp)
+        },
+        // Skip, 12 bit length.
+        3 => (SlpOp::Skip, try!(read_long_length(inst, data, &mut next)), 0),
+        6 => {
+            let len = try!(read_length(inst, data, &mut next));
+            (SlpOp::PlayerColorList, len, len)
+        },
+        7 => (SlpOp::Fill, try!(read_length(inst, data, &mut next)), bpp),
+        0x0a => (SlpOp::PlayerColorFill, try!(read_length(inst, data, &mut next)), 1),
+        0x0b => (SlpOp::Shadow, try!(read_length(inst, data, &mut next)), 0),
+        0x0e => match inst {
+            // Forward and reverse draw hints for mirrored sprites, and
+            // normal and alternate shadow table selection. None of them
+            // produce pixels.
+            0x0e | 0x1e | 0x2e | 0x3e => (SlpOp::Hint, 0, 0),
+            // Player color outline, single pixel and span.
+            0x4e => (SlpOp::PlayerOutline, 1, 0),
+            0x5e => (SlpOp::PlayerOutline, try!(read_span_length(data, &mut next)), 0),
+            // Black outline, single pixel and span.
+            0x6e => (SlpOp::BlackOutline, 1, 0),
+            0x7e => (SlpOp::BlackOutline, try!(read_span_length(data, &mut next)), 0),
+            _ => return Err(SlpError::UnknownCommand(inst, offset + pos))
+        },
+        _ => (SlpOp::EndOfRow, 0, 0)
+    };
+
+    Ok(Command {
+        op: op,
+        len: len,
+        data: try!(data.get(next..next + bytes).ok_or(SlpError::Truncated)),
+        next: next + bytes
+    })
+}
+
+#[inline(always)]
+fn read_byte(data: &[u8], pos: usize) -> Result<u8, SlpError> {
+    data.get(pos).cloned().ok_or(SlpError::Truncated)
+}
+
+/// Reads the length byte of an outline span.
+#[inline(always)]
+fn read_span_length(data: &[u8], pos: &mut usize) -> Result<usize, SlpError> {
+    *pos += 1;
+    Ok(try!(read_byte(data, *pos - 1)) as usize)
+}
+
+/// Reads the length of a command with a 4 bit length, which is stored in
+/// the next byte when zero.
+#[inline(always)]
+fn read_length(op: u8, data: &[u8], pos: &mut usize) -> Result<usize, SlpError> {
+    let len = op >> 4;
+    if len == 0u8 {
+        read_span_length(data, pos)
+    } else {
+        Ok(len as usize)
+    }
+}
+
+/// Reads the length of a command with a 12 bit length.
+#[inline(always)]
+fn read_long_length(op: u8, data: &[u8], pos: &mut usize) -> Result<usize, SlpError> {
+    *pos += 1;
+    Ok(((op as usize & 0xf0) << 4) + try!(read_byte(data, *pos - 1)) as usize)
+}
+
+/// Moves `x` past a span of `len` pixels in row `y`, failing if the span
+/// ends past the width of the frame.
+#[inline(always)]
+fn advance(y: usize, x: usize, len: usize, width: usize) -> Result<usize, SlpError> {
+    if x + len > width {
+        Err(SlpError::RowOverflow(y))
+    } else {
+        Ok(x + len)
+    }
+}
+
+#[inline(always)]
+fn palette_color(pal: &Palette, idx: usize) -> Result<[u8; 4], SlpError> {
+    match pal.colors.get(idx) {
+        Some(col) => Ok([col.0, col.1, col.2, 0xff]),
+        None => Err(SlpError::PaletteIndex(idx))
+    }
+}
+
+/// Converts a pixel of a `Colors` or `Fill` span to RGBA.
+#[inline(always)]
+fn pixel_color(px: &[u8], kind: SlpFrameKind, pal: &Palette) -> Result<[u8; 4], SlpError> {
+    match kind {
+        SlpFrameKind::Palette => palette_color(pal, px[0] as usize),
+        SlpFrameKind::Bgra32 => Ok([px[2], px[1], px[0], px[3]]),
+        SlpFrameKind::Shadow => Ok([0, 0, 0, px[0]]),
+        SlpFrameKind::DamageMask => Ok([px[0], px[0], px[0], 0xff])
+    }
+}
+
+#[derive(Debug)]
+pub struct SlpObject {
+    instructions: Box<[u8]>,
+    /// File offset of the first byte of `instructions`.
+    data_offset: u32,
+    offsets: Vec<u32>,
+    skips: Box<[(u16, u16)]>,
+    pub width: i32,
+    pub height: i32,
+    hotspot: (i32, i32),
+    properties: u32,
+    palette_offset: u32,
+    delta: bool,
+    pub kind: SlpFrameKind
+}
+
+impl SlpObject {
+    /// See `SlpEntry::hotspot`.
+    pub fn hotspot(&self) -> (i32, i32) {
+        self.hotspot
+    }
+
+    /// See `SlpEntry::properties`.
+    pub fn properties(&self) -> u32 {
+        self.properties
+    }
+
+    /// See `SlpEntry::palette_offset`.
+    pub fn palette_offset(&self) -> u32 {
+        self.palette_offset
+    }
+
+    /// Length of a buffer holding the whole frame at `bytes_per_pixel`
+    /// bytes per pixel.
+    ///
+    /// Fails with `SlpError::BadDimensions` if the frame is larger than an
+    /// SLP file can hold or the length overflows.
+    pub fn buffer_len(&self, bytes_per_pixel: usize) -> Result<usize, SlpError> {
+        let bad = SlpError::BadDimensions(self.width, self.height);
+        if self.width < 0 || self.height < 0 || self.width as u32 > MAX_SIZE || self.height as u32 > MAX_SIZE {
+            return Err(bad);
+        }
+
+        (self.width as usize).checked_mul(self.height as usize)
+            .and_then(|len| len.checked_mul(bytes_per_pixel))
+            .ok_or(bad)
+    }
+
+    /// Whether this is a delta frame from a `4.x` file, which only holds the
+    /// pixels that changed since the previous frame. It must be drawn over
+    /// the previous frame, as `SlpReader::read_image` does, and all other
+    /// pixels are the previous frame's.
+    pub fn is_delta(&self) -> bool {
+        self.delta
+    }
+
+    /// Left and right margins of each row, the number of transparent pixels
+    /// before the first and after the last drawn pixel. Rows that are fully
+    /// transparent have both margins set to `0x8000`.
+    pub fn margins(&self) -> &[(u16, u16)] {
+        &self.skips
+    }
+
+    /// Whether row `y` is marked as fully transparent.
+    fn is_empty_row(&self, y: usize) -> bool {
+        let (left, right) = self.skips[y];
+        left == 0x8000 || right == 0x8000
+    }
+
+    /// Left margin of row `y`.
+    fn left(&self, y: usize) -> usize {
+        if self.is_empty_row(y) {
+            0
+        } else {
+            self.skips[y].0 as usize
+        }
+    }
+
+    /// Commands of row `y` through the end of the frame, and their offset
+    /// in the file.
+    fn row(&self, y: usize) -> (&[u8], usize) {
+        let start = (self.offsets[y] - self.data_offset) as usize;
+        (&self.instructions[start..], self.offsets[y] as usize)
+    }
+}
+
+pub struct SlpReader<R: Read + Seek> {
+    reader: R,
+    version: SlpVersion,
+    tables: Arc<SlpTables>
+}
+
+/// Everything `SlpReader::new` reads up front, shared by `with_stream`.
+struct SlpTables {
+    header: SlpHeader,
+    entries: Vec<SlpEntry>,
+    secondary: Vec<SlpEntry>,
+    /// Outline and data offsets of all frames, sorted and deduplicated.
+    /// The commands of a frame end at the first one past its last row.
+    tables_at: Vec<u32>
+}
+
+impl<R: Read + Seek> SlpReader<R> {
+    pub fn new(mut reader: R) -> Result<SlpReader<R>, SlpError> {
+        let header = try!(read_slp_header(&mut reader));
+        let version = SlpVersion::from_bytes(header.version);
+
+        let (entries, secondary) = if version.is_v4() {
+            try!(reader.seek(SeekFrom::Start(header.offset_main as _)));
+            let entries = try!(read_slp_entries(header.count, &mut reader));
+
+            let secondary = if header.offset_secondary != 0 {
+                try!(reader.seek(SeekFrom::Start(header.offset_secondary as _)));
+                try!(read_slp_entries(header.count, &mut reader))
+            } else {
+                Vec::new()
+            };
+
+            (entries, secondary)
+        } else {
+            (try!(read_slp_entries(header.count, &mut reader)), Vec::new())
+        };
+
+        let mut tables_at: Vec<u32> = entries.iter().chain(secondary.iter())
+            .flat_map(|e| iter::once(e.outline_offset).chain(iter::once(e.data_offset)))
+            .collect();
+        tables_at.sort();
+        tables_at.dedup();
+
+        Ok(SlpReader {
+            reader: reader,
+            version: version,
+            tables: Arc::new(SlpTables {
+                header: header,
+                entries: entries,
+                secondary: secondary,
+                tables_at: tables_at
+            })
+        })
+    }
+
+    /// Creates a reader for another stream over the same file, sharing the
+    /// headers this one has read instead of parsing them again. Useful to
+    /// decode frames of one file on several threads.
+    pub fn with_stream<S: Read + Seek>(&self, reader: S) -> SlpReader<S> {
+        SlpReader {
+            reader: reader,
+            version: self.version,
+            tables: self.tables.clone()
+        }
+    }
+
+    pub fn version(&self) -> SlpVersion {
+        self.version
+    }
+
+    /// Comment from the header, e.g. `ArtDesk 1.00 SLP Writer`. Always
+    /// empty for `4.x` files, which have no comment.
+    pub fn comment(&self) -> String {
+        String::from_utf8_lossy(&self.tables.header.comment).trim_right_matches('\0').to_string()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.entries.len()
+    }
+
+    /// Headers of all main frames, read when the file was opened.
+    pub fn frames(&self) -> &[SlpEntry] {
+        &self.tables.entries
+    }
+
+    /// Headers of all secondary frames, see `secondary_len`.
+    pub fn secondary_frames(&self) -> &[SlpEntry] {
+        &self.tables.secondary
+    }
+
+    /// Number of secondary frames, which hold the shadows or damage masks
+    /// of the main frames in `4.x` files.
+    pub fn secondary_len(&self) -> usize {
+        self.tables.secondary.len()
+    }
+
+    pub fn read_shape(&mut self, index: usize) -> Result<SlpObject, SlpError> {
+        let entry = try!(self.tables.entries.get(index).cloned().ok_or(SlpError::FrameNotFound(index)));
+        let kind = if self.version.is_v4() && entry.properties & PROPERTY_32BIT == PROPERTY_32BIT {
+            SlpFrameKind::Bgra32
+        } else {
+            SlpFrameKind::Palette
+        };
+
+        self.read_entry(entry, kind, self.is_delta(index))
+    }
+
+    /// Reads the secondary frame belonging to main frame `index`.
+    pub fn read_secondary_shape(&mut self, index: usize) -> Result<SlpObject, SlpError> {
+        let entry = try!(self.tables.secondary.get(index).cloned().ok_or(SlpError::FrameNotFound(index)));
+        let kind = if self.version == SlpVersion::V4_1 {
+            SlpFrameKind::DamageMask
+        } else {
+            SlpFrameKind::Shadow
+        };
+
+        self.read_entry(entry, kind, false)
+    }
+
+    /// Whether main frame `index` is a delta frame, see
+    /// `SlpObject::is_delta`.
+    pub fn is_delta(&self, index: usize) -> bool {
+        self.version.is_v4() && self.tables.entries.get(index).map_or(false, |entry| entry.properties & PROPERTY_DELTA != 0)
+    }
+
+    /// The frame that delta frame `index` is ultimately drawn over, the
+    /// closest earlier frame that is not a delta frame. For other frames,
+    /// `index` itself.
+    pub fn keyframe(&self, index: usize) -> usize {
+        let mut key = index;
+        while key > 0 && self.is_delta(key) {
+            key -= 1;
+        }
+
+        key
+    }
+
+    /// Id of the palette that 8-bit frames of `4.x` files index into, which
+    /// may differ from the game's default palette. `None` for older files.
+    pub fn palette_id(&self) -> Option<u32> {
+        if self.version.is_v4() {
+            Some(self.tables.header.palette)
+        } else {
+            None
+        }
+    }
+
+    fn read_entry(&mut self, entry: SlpEntry, kind: SlpFrameKind, delta: bool) -> Result<SlpObject, SlpError> {
+        if entry.width < 0 || entry.height < 0 || entry.width as u32 > MAX_SIZE || entry.height as u32 > MAX_SIZE {
+            return Err(SlpError::BadDimensions(entry.width, entry.height));
+        }
+
+        // Each row has 4 bytes in both the outline and the offset table,
+        // which also keeps bogus heights from allocating beyond the file
+        // size. The tables are usually adjacent, but need not be.
+        let end = try!(self.reader.seek(SeekFrom::End(0)));
+        for &offset in &[entry.outline_offset, entry.data_offset] {
+            if offset as u64 + entry.height as u64 * 4 > end {
+                return Err(SlpError::BadOffset(offset));
+            }
+        }
+
+        try!(self.reader.seek(SeekFrom::Start(entry.outline_offset as _)));
+
+        let mut skips = Vec::new();
+        for _ in 0..entry.height {
+            let left = try!(self.reader.read_u16::<LittleEndian>());
+            let right = try!(self.reader.read_u16::<LittleEndian>());
+
+            skips.push((left, right));
+        }
+
+        try!(self.reader.seek(SeekFrom::Start(entry.data_offset as _)));
+
+        let mut offsets = Vec::new();
+        for _ in 0..entry.height {
+            let offset = try!(self.reader.read_u32::<LittleEndian>());
+            if offset as u64 >= end {
+                return Err(SlpError::BadOffset(offset));
+            }
+
+            offsets.push(offset);
+        }
+
+        let start = offsets.iter().cloned().min().unwrap_or(0);
+        let instructions = if offsets.is_empty() {
+            Vec::new().into_boxed_slice()
+        } else {
+            // The commands end where the next table of any frame starts,
+            // or at the end of the file.
+            let last = offsets.iter().cloned().max().unwrap_or(0);
+            let tables_at = &self.tables.tables_at;
+            let next = match tables_at.binary_search(&last) {
+                Ok(i) => i + 1,
+                Err(i) => i
+            };
+            let data_end = tables_at.get(next).map_or(end, |&offset| ::std::cmp::min(offset as u64, end));
+
+            try!(self.reader.seek(SeekFrom::Start(start as _)));
+            let mut vec = vec![0u8; (data_end - start as u64) as _];
+            try!(self.reader.read_exact(&mut vec));
+            vec.into_boxed_slice()
+        };
+
+        Ok(SlpObject {
+            instructions: instructions,
+            data_offset: start,
+            offsets: offsets,
+            skips: skips.into_boxed_slice(),
+            width: entry.width,
+            height: entry.height,
+            hotspot: (entry.hotspot_x, entry.hotspot_y),
+            properties: entry.properties,
+            palette_offset: entry.palette_offset,
+            delta: delta,
+            kind: kind
+        })
+    }
+}
+
+
+fn read_slp_header<R: Read>(reader: &mut R) -> Result<SlpHeader, ::std::io::Error> {
+    let mut version: [u8; 4] = [0u8; 4];
+    try!(reader.read_exact(&mut version));
+
+    if SlpVersion::from_bytes(version).is_v4() {
+        let count = try!(reader.read_u16::<LittleEndian>());
+        let _ty = try!(reader.read_u16::<LittleEndian>());
+        let _directions = try!(reader.read_u16::<LittleEndian>());
+        let _frames_per_direction = try!(reader.read_u16::<LittleEndian>());
+        let palette = try!(reader.read_u32::<LittleEndian>());
+        let offset_main = try!(reader.read_u32::<LittleEndian>());
+        let offset_secondary = try!(reader.read_u32::<LittleEndian>());
+
+        let mut padding: [u8; 8] = [0u8; 8];
+        try!(reader.read_exact(&mut padding));
+
+        return Ok(SlpHeader {
+            version: version,
+            count: count as _,
+            comment: [0u8; 24],
+            palette: palette,
+            offset_main: offset_main,
+            offset_secondary: offset_secondary
+        });
+    }
+
+    let count = try!(reader.read_u32::<LittleEndian>());
+
+    let mut comment: [u8; 24] = [0u8; 24];
+    try!(reader.read_exact(&mut comment));
+
+    Ok(SlpHeader {
+        version: version,
+        count: count,
+        comment: comment,
+        palette: 0,
+        offset_main: 0,
+        offset_secondary: 0
+    })
+}
+
+fn read_slp_entries<R: Read>(count: u32, reader: &mut R) -> Result<Vec<SlpEntry>, ::std::io::Error> {
+    let mut entries: Vec<SlpEntry> = vec![];
+
+    for _ in 0..count {
+        let data_offset = try!(reader.read_u32::<LittleEndian>());
+        let outline_offset = try!(reader.read_u32::<LittleEndian>());
+        let palette_offset = try!(reader.read_u32::<LittleEndian>());
+        let properties = try!(reader.read_u32::<LittleEndian>());
+        let width = try!(reader.read_i32::<LittleEndian>());
+        let height = try!(reader.read_i32::<LittleEndian>());
+        let hotspot_x = try!(reader.read_i32::<LittleEndian>());
+        let hotspot_y = try!(reader.read_i32::<LittleEndian>());
+
+        entries.push(SlpEntry {
+            data_offset: data_offset,
+            outline_offset: outline_offset,
+            palette_offset: palette_offset,
+            properties: properties,
+            width: width,
+            height: height,
+            hotspot_x: hotspot_x,
+            hotspot_y: hotspot_y
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use format::pal::Palette;
+    use super::*;
+
+    /// Rewrites the header of a `2.0N` file from `SlpWriter` as a `4.0X`
+    /// header, which has the same size, and sets the properties of each
+    /// frame.
+    fn to_v4(mut data: Vec<u8>, palette: u32, properties: &[u32]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"4.0X");
+        header.write_u16::<LittleEndian>(properties.len() as u16).unwrap();
+        header.write_u16::<LittleEndian>(0).unwrap();
+        header.write_u16::<LittleEndian>(1).unwrap();
+        header.write_u16::<LittleEndian>(properties.len() as u16).unwrap();
+        header.write_u32::<LittleEndian>(palette).unwrap();
+        header.write_u32::<LittleEndian>(32).unwrap();
+        header.write_u32::<LittleEndian>(0).unwrap();
+        header.extend_from_slice(&[0; 8]);
+        data[..32].copy_from_slice(&header);
+
+        for (i, &props) in properties.iter().enumerate() {
+            let pos = 32 + i * 32 + 12;
+            (&mut data[pos..pos + 4]).write_u32::<LittleEndian>(props).unwrap();
+        }
+
+        data
+    }
+
+    fn frame(pixels: &[SlpPixel]) -> SlpFrame {
+        SlpFrame {
+            width: 3,
+            height: 1,
+            hotspot: (0, 0),
+            pixels: pixels.to_vec().into_boxed_slice()
+        }
+    }
+
+    #[test]
+    fn delta_frames() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(frame(&[SlpPixel::Color(1), SlpPixel::Color(2), SlpPixel::Color(3)]));
+        writer.add_frame(frame(&[SlpPixel::Transparent, SlpPixel::Color(4), SlpPixel::Transparent]));
+        writer.add_frame(frame(&[SlpPixel::Color(5), SlpPixel::Transparent, SlpPixel::Transparent]));
+        writer.add_frame(frame(&[SlpPixel::Transparent, SlpPixel::Transparent, SlpPixel::Color(6)]));
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        let data = to_v4(data, 55, &[0, PROPERTY_DELTA, PROPERTY_DELTA, 0]);
+
+        let pal = Palette {
+            colors: (0..256).map(|i| (i as u8, 0, 0)).collect::<Vec<_>>().into_boxed_slice()
+        };
+        let mut reader = SlpReader::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(reader.version(), SlpVersion::V4_0);
+        assert_eq!(reader.palette_id(), Some(55));
+        assert_eq!((0..4).map(|i| reader.is_delta(i)).collect::<Vec<_>>(), vec![false, true, true, false]);
+        assert_eq!((0..4).map(|i| reader.keyframe(i)).collect::<Vec<_>>(), vec![0, 0, 0, 3]);
+        assert!(reader.read_shape(2).unwrap().is_delta());
+
+        let red = |image: ::image::RgbaImage| image.pixels().map(|px| (px.data[0], px.data[3])).collect::<Vec<_>>();
+        assert_eq!(red(reader.read_image(2, &pal, &SlpRasterOptions::default()).unwrap()), vec![(5, 0xff), (4, 0xff), (3, 0xff)]);
+        assert_eq!(red(reader.read_image(3, &pal, &SlpRasterOptions::default()).unwrap()), vec![(0, 0), (0, 0), (6, 0xff)]);
+    }
+
+    #[test]
+    fn player_options() {
+        let bases: Vec<usize> = (1..9).map(|player| SlpRasterOptions::for_player(player).unwrap().player_base).collect();
+        assert_eq!(bases, vec![16, 24, 32, 40, 48, 56, 64, 72]);
+        assert_eq!(SlpRasterOptions::for_player(8).unwrap().player_outline, 72);
+        assert_eq!(SlpRasterOptions::default().player_base, SlpRasterOptions::for_player(1).unwrap().player_base);
+
+        assert!(SlpRasterOptions::for_player(0).is_none());
+        assert!(SlpRasterOptions::for_player(9).is_none());
+        assert!(SlpRasterOptions::for_player(255).is_none());
+    }
+
+    #[test]
+    fn separate_row_offsets() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(frame(&[SlpPixel::Color(1), SlpPixel::Transparent, SlpPixel::Color(3)]));
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        let pal = Palette {
+            colors: (0..256).map(|i| (i as u8, 0, 0)).collect::<Vec<_>>().into_boxed_slice()
+        };
+        let expected = SlpDefaultRasterizer::rasterize_image(&SlpReader::new(Cursor::new(data.clone())).unwrap().read_shape(0).unwrap(), &pal, &SlpRasterOptions::default()).unwrap();
+
+        // Move the row offset table to the end of the file.
+        let data_offset = u32_at(&data, 32) as usize;
+        let table = data[data_offset..data_offset + 4].to_vec();
+        let moved = data.len() as u32;
+        data.extend_from_slice(&table);
+        (&mut data[32..36]).write_u32::<LittleEndian>(moved).unwrap();
+        (&mut data[data_offset..data_offset + 4]).write_u32::<LittleEndian>(0xdeadbeef).unwrap();
+
+        let obj = SlpReader::new(Cursor::new(data.clone())).unwrap().read_shape(0).unwrap();
+        let image = SlpDefaultRasterizer::rasterize_image(&obj, &pal, &SlpRasterOptions::default()).unwrap();
+        assert_eq!(image.into_raw(), expected.into_raw());
+
+        (&mut data[32..36]).write_u32::<LittleEndian>(moved + 1).unwrap();
+        match SlpReader::new(Cursor::new(data)).unwrap().read_shape(0) {
+            Err(SlpError::BadOffset(offset)) => assert_eq!(offset, moved + 1),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        data[pos] as u32 | (data[pos + 1] as u32) << 8 | (data[pos + 2] as u32) << 16 | (data[pos + 3] as u32) << 24
+    }
+
+    #[test]
+    fn oversized_frames() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(frame(&[SlpPixel::Color(1); 3]));
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+
+        // Width and height of the first frame.
+        for &(pos, value) in &[(48, MAX_SIZE + 1), (52, MAX_SIZE + 1), (48, u32::max_value())] {
+            let mut data = data.clone();
+            (&mut data[pos..pos + 4]).write_u32::<LittleEndian>(value).unwrap();
+
+            let mut reader = SlpReader::new(Cursor::new(data)).unwrap();
+            match reader.read_shape(0) {
+                Err(SlpError::BadDimensions(..)) => {},
+                other => panic!("{:?}", other.map(|_| ()))
+            }
+        }
+
+        let mut obj = SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap();
+        assert_eq!(obj.buffer_len(4).unwrap(), 12);
+        obj.width = MAX_SIZE as i32 + 1;
+        let pal = Palette { colors: vec![(0, 0, 0); 256].into_boxed_slice() };
+        match SlpDefaultRasterizer::rasterize_image(&obj, &pal, &SlpRasterOptions::default()) {
+            Err(SlpError::BadDimensions(..)) => {},
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        match SlpLayeredRasterizer::rasterize(&obj, &pal) {
+            Err(SlpError::BadDimensions(..)) => {},
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        match SlpIndexedRasterizer::rasterize(&obj, &SlpRasterOptions::default(), 0) {
+            Err(SlpError::BadDimensions(..)) => {},
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn delta_flag_ignored_before_v4() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(frame(&[SlpPixel::Color(1); 3]));
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        (&mut data[44..48]).write_u32::<LittleEndian>(PROPERTY_DELTA).unwrap();
+
+        let reader = SlpReader::new(Cursor::new(data)).unwrap();
+        assert!(!reader.is_delta(0));
+        assert_eq!(reader.palette_id(), None);
+    }
+}
@@ -0,0 +1,297 @@
+use super::{SlpObject, SlpError, SlpOp, read_command, advance};
+
+/// What the pixels of an `SlpSpan` are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpSpanKind<'a> {
+    /// Transparent pixels.
+    Skip,
+    /// One pixel per `SlpFrameKind::bytes_per_pixel` bytes, palette indices
+    /// in 8-bit frames.
+    Colors(&'a [u8]),
+    /// A single pixel, in the same format as `Colors`, repeated over the
+    /// span.
+    Fill(&'a [u8]),
+    /// Indices into the player's palette band, one per pixel.
+    PlayerColors(&'a [u8]),
+    /// A single index into the player's palette band, repeated over the
+    /// span.
+    PlayerFill(u8),
+    Shadow,
+    PlayerOutline,
+    BlackOutline
+}
+
+/// A run of pixels of one kind in a row of a frame, the form in which
+/// frames are handed to rasterizers and other backends.
+///
+/// Spans borrow their pixel data from the frame's commands.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlpSpan<'a> {
+    /// Column of the first pixel.
+    pub x: usize,
+    pub len: usize,
+    pub kind: SlpSpanKind<'a>
+}
+
+/// Iterator over the spans of a row, created by `SlpObject::row_spans`.
+///
+/// Spans are returned left to right and never overlap or extend past the
+/// width of the frame. Pixels before the first span, between spans and
+/// after the last one are transparent, like those of `Skip` spans. The
+/// iterator ends after the first error.
+pub struct SlpRowSpans<'a> {
+    data: &'a [u8],
+    /// Position of the next command in `data`.
+    pos: usize,
+    /// File offset of `data`.
+    offset: usize,
+    y: usize,
+    x: usize,
+    width: usize,
+    bpp: usize,
+    done: bool
+}
+
+impl SlpObject {
+    /// Decodes the commands of row `y` into spans. Rows marked as fully
+    /// transparent have none.
+    ///
+    /// Panics if `y` is not less than the frame's height.
+    pub fn row_spans(&self, y: usize) -> SlpRowSpans {
+        let empty = self.is_empty_row(y);
+        let (data, offset) = if empty {
+            (&[][..], 0)
+        } else {
+            self.row(y)
+        };
+
+        SlpRowSpans {
+            data: data,
+            pos: 0,
+            offset: offset,
+            y: y,
+            x: self.left(y),
+            width: self.width as usize,
+            bpp: self.kind.bytes_per_pixel(),
+            done: empty
+        }
+    }
+
+    /// Decodes every row of the frame into spans.
+    pub fn spans(&self) -> Result<Vec<Vec<SlpSpan>>, SlpError> {
+        (0..self.height as usize).map(|y| self.row_spans(y).collect()).collect()
+    }
+}
+
+impl<'a> Iterator for SlpRowSpans<'a> {
+    type Item = Result<SlpSpan<'a>, SlpError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.next_span() {
+                Ok(Some(span)) => return Some(Ok(span)),
+                Ok(None) => {},
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> SlpRowSpans<'a> {
+    /// Decodes the next command, returning `None` for commands that draw
+    /// nothing.
+    #[inline(always)]
+    fn next_span(&mut self) -> Result<Option<SlpSpan<'a>>, SlpError> {
+        // Only possible with a left margin wider than the frame.
+        if self.x > self.width {
+            return Err(SlpError::RowOverflow(self.y));
+        }
+
+        let command = try!(read_command(self.data, self.pos, self.offset, self.bpp));
+        self.pos = command.next;
+
+        let kind = match command.op {
+            SlpOp::ColorList => SlpSpanKind::Colors(command.data),
+            SlpOp::Skip => SlpSpanKind::Skip,
+            SlpOp::PlayerColorList => SlpSpanKind::PlayerColors(command.data),
+            SlpOp::Fill => SlpSpanKind::Fill(command.data),
+            SlpOp::PlayerColorFill => SlpSpanKind::PlayerFill(command.data[0]),
+            SlpOp::Shadow => SlpSpanKind::Shadow,
+            SlpOp::PlayerOutline => SlpSpanKind::PlayerOutline,
+            SlpOp::BlackOutline => SlpSpanKind::BlackOutline,
+            SlpOp::Hint => return Ok(None),
+            SlpOp::EndOfRow => {
+                self.done = true;
+                return Ok(None);
+            }
+        };
+
+        let x = self.x;
+        self.x = try!(advance(self.y, x, command.len, self.width));
+
+        if command.len == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(SlpSpan {
+            x: x,
+            len: command.len,
+            kind: kind
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use format::pal::Palette;
+    use format::slp::*;
+    use super::super::{SHADOW_COLOR, PLAYER_OUTLINE_ALPHA, BLACK_OUTLINE_COLOR};
+    use super::super::layers::{OUTLINE_PLAYER, OUTLINE_BLACK};
+
+    const WIDTH: usize = 12;
+
+    /// Rows covering every kind of span, with runs long enough for the
+    /// writer to use fill commands.
+    fn frame() -> SlpFrame {
+        use format::slp::SlpPixel::*;
+
+        let rows: Vec<Vec<SlpPixel>> = vec![
+            vec![Transparent, Color(1), Color(2), Color(3), Color(3), Color(3), Color(3), Color(3), Transparent, Transparent, Color(4), Transparent],
+            vec![Player(5), Player(5), Player(5), Player(5), Player(5), Player(0), Player(1), Player(2), Color(9), Transparent, Player(7), Color(10)],
+            vec![Transparent; WIDTH],
+            vec![Shadow, Shadow, Shadow, PlayerOutline, PlayerOutline, BlackOutline, BlackOutline, BlackOutline, Transparent, Shadow, Color(0), Color(255)]
+        ];
+
+        SlpFrame {
+            width: WIDTH as u32,
+            height: rows.len() as u32,
+            hotspot: (0, 0),
+            pixels: rows.into_iter().flat_map(|row| row.into_iter()).collect::<Vec<_>>().into_boxed_slice()
+        }
+    }
+
+    fn read(frame: &SlpFrame) -> SlpObject {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(frame.clone());
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        SlpReader::new(Cursor::new(data)).unwrap().read_shape(0).unwrap()
+    }
+
+    /// Expands the spans of each row back into pixels.
+    fn pixels(obj: &SlpObject) -> Vec<SlpPixel> {
+        let mut out = vec![SlpPixel::Transparent; obj.width as usize * obj.height as usize];
+
+        for (y, row) in obj.spans().unwrap().into_iter().enumerate() {
+            let mut end = 0;
+
+            for span in row {
+                assert!(span.x >= end && span.len > 0 && span.x + span.len <= WIDTH, "{:?}", span);
+                end = span.x + span.len;
+
+                for (i, px) in out[y * WIDTH + span.x..y * WIDTH + end].iter_mut().enumerate() {
+                    *px = match span.kind {
+                        SlpSpanKind::Skip => SlpPixel::Transparent,
+                        SlpSpanKind::Colors(data) => SlpPixel::Color(data[i]),
+                        SlpSpanKind::Fill(data) => SlpPixel::Color(data[0]),
+                        SlpSpanKind::PlayerColors(data) => SlpPixel::Player(data[i]),
+                        SlpSpanKind::PlayerFill(idx) => SlpPixel::Player(idx),
+                        SlpSpanKind::Shadow => SlpPixel::Shadow,
+                        SlpSpanKind::PlayerOutline => SlpPixel::PlayerOutline,
+                        SlpSpanKind::BlackOutline => SlpPixel::BlackOutline
+                    };
+                }
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn spans_round_trip() {
+        let frame = frame();
+        let obj = read(&frame);
+
+        assert_eq!(&pixels(&obj)[..], &frame.pixels[..]);
+        assert_eq!(obj.row_spans(2).count(), 0);
+
+        let kinds: Vec<SlpSpanKind> = obj.spans().unwrap().into_iter().flat_map(|row| row.into_iter()).map(|span| span.kind).collect();
+        assert!(kinds.iter().any(|kind| match *kind { SlpSpanKind::Fill(_) => true, _ => false }));
+        assert!(kinds.iter().any(|kind| match *kind { SlpSpanKind::Colors(_) => true, _ => false }));
+        assert!(kinds.iter().any(|kind| match *kind { SlpSpanKind::PlayerFill(_) => true, _ => false }));
+        assert!(kinds.iter().any(|kind| match *kind { SlpSpanKind::PlayerColors(_) => true, _ => false }));
+    }
+
+    #[test]
+    fn rasterizers_agree() {
+        let pal = Palette {
+            colors: (0..256).map(|i| (i as u8, 255 - i as u8, (i * 7) as u8)).collect::<Vec<_>>().into_boxed_slice()
+        };
+        let options = SlpRasterOptions::for_player(3).unwrap();
+        let frame = frame();
+        let obj = read(&frame);
+        let pixels = pixels(&obj);
+
+        let color = |px: SlpPixel| -> [u8; 4] {
+            let rgba = |idx: usize| [pal.colors[idx].0, pal.colors[idx].1, pal.colors[idx].2, 0xff];
+            match px {
+                SlpPixel::Transparent => [0; 4],
+                SlpPixel::Color(idx) => rgba(idx as usize),
+                SlpPixel::Player(idx) => rgba(options.player_base + idx as usize),
+                SlpPixel::Shadow => SHADOW_COLOR,
+                SlpPixel::PlayerOutline => {
+                    let mut col = rgba(options.player_outline);
+                    col[3] = PLAYER_OUTLINE_ALPHA;
+                    col
+                },
+                SlpPixel::BlackOutline => BLACK_OUTLINE_COLOR
+            }
+        };
+        let expected: Vec<u8> = pixels.iter().flat_map(|&px| color(px).to_vec()).collect();
+
+        let image = SlpDefaultRasterizer::rasterize_image(&obj, &pal, &options).unwrap();
+        assert_eq!(&image.into_raw()[..], &expected[..]);
+
+        let table = SlpColorTable::new(&pal, &options).unwrap();
+        let mut fast = vec![0u8; expected.len()];
+        SlpFastRasterizer::rasterize(&mut fast, WIDTH * 4, &obj, &table).unwrap();
+        assert_eq!(fast, expected);
+
+        // Layers hold shadows and outlines apart from the colors.
+        let layers = SlpLayeredRasterizer::rasterize(&obj, &pal).unwrap();
+        let flat = layers.flatten(&pal, &options).unwrap();
+        for (i, &px) in pixels.iter().enumerate() {
+            match px {
+                SlpPixel::Color(_) | SlpPixel::Player(_) => assert_eq!(&flat[i * 4..i * 4 + 4], &color(px)[..]),
+                _ => assert_eq!(&flat[i * 4..i * 4 + 4], &[0; 4])
+            }
+            assert_eq!(layers.shadow[i], if px == SlpPixel::Shadow { 0xff } else { 0 });
+            assert_eq!(layers.outline[i], match px {
+                SlpPixel::PlayerOutline => OUTLINE_PLAYER,
+                SlpPixel::BlackOutline => OUTLINE_BLACK,
+                _ => 0
+            });
+        }
+
+        // The transparent index must not be drawn, so leave out index 0.
+        let mut without_zero = frame.clone();
+        without_zero.pixels[WIDTH * 3 + 10] = SlpPixel::Transparent;
+        let obj = read(&without_zero);
+        let indexed = SlpIndexedRasterizer::rasterize(&obj, &options, 0).unwrap();
+        for (i, &px) in without_zero.pixels.iter().enumerate() {
+            assert_eq!(indexed[i], match px {
+                SlpPixel::Color(idx) => idx,
+                SlpPixel::Player(idx) => (options.player_base + idx as usize) as u8,
+                _ => 0
+            });
+        }
+    }
+}
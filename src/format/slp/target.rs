@@ -0,0 +1,182 @@
+use std::io::{Read, Seek};
+use std::ops::DerefMut;
+use image::RgbaImage;
+
+use format::pal::Palette;
+use super::{SlpObject, SlpReader, SlpError, SlpRasterOptions, SlpDefaultRasterizer, SpanColors};
+
+/// An RGBA pixel buffer that frames can be drawn into, see
+/// `SlpDefaultRasterizer::draw`.
+pub trait SlpTarget {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    /// RGBA pixels of row `y`, `width() * 4` bytes long.
+    fn row_mut(&mut self, y: usize) -> &mut [u8];
+}
+
+/// RGBA pixels in a slice with rows `stride` bytes apart, such as a
+/// rectangle of a larger canvas.
+pub struct SlpSliceTarget<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize
+}
+
+impl<'a> SlpSliceTarget<'a> {
+    /// Returns `None` if `data` is too small to hold `height` rows of
+    /// `width` pixels.
+    pub fn new(data: &'a mut [u8], width: usize, height: usize, stride: usize) -> Option<SlpSliceTarget<'a>> {
+        if stride < width * 4 || (height > 0 && data.len() < (height - 1) * stride + width * 4) {
+            return None;
+        }
+
+        Some(SlpSliceTarget {
+            data: data,
+            width: width,
+            height: height,
+            stride: stride
+        })
+    }
+
+    /// The `width` by `height` rectangle at `x`, `y` of this target, or
+    /// `None` if it does not fit.
+    pub fn sub_rect(&mut self, x: usize, y: usize, width: usize, height: usize) -> Option<SlpSliceTarget> {
+        if x + width > self.width || y + height > self.height {
+            return None;
+        }
+
+        let start = ::std::cmp::min(y * self.stride + x * 4, self.data.len());
+        SlpSliceTarget::new(&mut self.data[start..], width, height, self.stride)
+    }
+}
+
+impl<'a> SlpTarget for SlpSliceTarget<'a> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn row_mut(&mut self, y: usize) -> &mut [u8] {
+        let start = y * self.stride;
+        &mut self.data[start..start + self.width * 4]
+    }
+}
+
+impl SlpTarget for RgbaImage {
+    fn width(&self) -> usize {
+        RgbaImage::width(self) as usize
+    }
+
+    fn height(&self) -> usize {
+        RgbaImage::height(self) as usize
+    }
+
+    fn row_mut(&mut self, y: usize) -> &mut [u8] {
+        let len = RgbaImage::width(self) as usize * 4;
+        &mut self.deref_mut()[y * len..(y + 1) * len]
+    }
+}
+
+/// How drawn pixels are combined with the pixels already in a target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpBlendMode {
+    /// Overwrite the target's pixels, including their alpha.
+    Replace,
+    /// Draw over the target's pixels according to the drawn pixel's alpha,
+    /// so shadows and outlines darken what is below them.
+    Alpha
+}
+
+impl SlpBlendMode {
+    /// Combines `src` into the RGBA pixel `dst`.
+    #[inline(always)]
+    pub fn blend(self, dst: &mut [u8], src: [u8; 4]) {
+        let sa = src[3] as u32;
+
+        if self == SlpBlendMode::Replace || sa == 0xff {
+            dst.copy_from_slice(&src);
+            return;
+        }
+
+        // Straight alpha "over", with both alphas scaled by 255.
+        let da = dst[3] as u32 * (0xff - sa);
+        let a = sa * 0xff + da;
+        if a == 0 {
+            return;
+        }
+
+        for i in 0..3 {
+            dst[i] = ((src[i] as u32 * sa * 0xff + dst[i] as u32 * da) / a) as u8;
+        }
+        dst[3] = ((a + 0x7f) / 0xff) as u8;
+    }
+}
+
+impl SlpDefaultRasterizer {
+    /// Draws a frame into `target` with its top left corner at `x`, `y`,
+    /// in the same colors as `rasterize_with`. Pixels outside the target are
+    /// clipped, and rows entirely outside it are not decoded.
+    ///
+    /// To place a sprite at a position, draw it at the position minus
+    /// `SlpObject::hotspot`.
+    pub fn draw<T: SlpTarget>(target: &mut T, obj: &SlpObject, pal: &Palette, options: &SlpRasterOptions, x: i32, y: i32, blend: SlpBlendMode) -> Result<(), SlpError> {
+        let colors = try!(SpanColors::new(pal, obj.kind, options));
+        let height = target.height() as i32;
+
+        for fy in 0..obj.height as usize {
+            let ty = y + fy as i32;
+            if ty < 0 || ty >= height {
+                continue;
+            }
+
+            let row = target.row_mut(ty as usize);
+            for span in obj.row_spans(fy) {
+                try!(colors.draw(&try!(span), |fx, col| {
+                    let tx = x + fx as i32;
+                    if tx >= 0 && (tx as usize) * 4 < row.len() {
+                        let pos = tx as usize * 4;
+                        blend.blend(&mut row[pos..pos + 4], col);
+                    }
+
+                    Ok(())
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rasterizes a frame into a new image of its size.
+    pub fn rasterize_image(obj: &SlpObject, pal: &Palette, options: &SlpRasterOptions) -> Result<RgbaImage, SlpError> {
+        try!(obj.buffer_len(4));
+        let mut image = RgbaImage::new(obj.width as u32, obj.height as u32);
+        try!(SlpDefaultRasterizer::draw(&mut image, obj, pal, options, 0, 0, SlpBlendMode::Replace));
+
+        Ok(image)
+    }
+}
+
+impl<R: Read + Seek> SlpReader<R> {
+    /// Reads and rasterizes main frame `index` into a new image. Delta
+    /// frames are drawn over the frames before them, back to their
+    /// keyframe, which must all be the same size.
+    pub fn read_image(&mut self, index: usize, pal: &Palette, options: &SlpRasterOptions) -> Result<RgbaImage, SlpError> {
+        let key = self.keyframe(index);
+        let mut image = try!(SlpDefaultRasterizer::rasterize_image(&try!(self.read_shape(key)), pal, options));
+
+        for delta in key + 1..index + 1 {
+            let obj = try!(self.read_shape(delta));
+            if (obj.width as u32, obj.height as u32) != image.dimensions() {
+                return Err(SlpError::BadDimensions(obj.width, obj.height));
+            }
+
+            try!(SlpDefaultRasterizer::draw(&mut image, &obj, pal, options, 0, 0, SlpBlendMode::Replace));
+        }
+
+        Ok(image)
+    }
+}
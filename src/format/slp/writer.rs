@@ -0,0 +1,563 @@
+use std::io::Write;
+use std::collections::HashMap;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use format::pal::Palette;
+use super::{SlpError, MAX_SIZE, PLAYER_BAND_SIZE};
+use super::layers::{OUTLINE_PLAYER, OUTLINE_BLACK};
+
+/// A single pixel of a frame to be encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlpPixel {
+    Transparent,
+    /// Palette index.
+    Color(u8),
+    /// Index into the player's palette band.
+    Player(u8),
+    Shadow,
+    PlayerOutline,
+    BlackOutline
+}
+
+/// A frame to be encoded by `SlpWriter`.
+#[derive(Debug, Clone)]
+pub struct SlpFrame {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (i32, i32),
+    pub pixels: Box<[SlpPixel]>
+}
+
+impl SlpFrame {
+    /// Creates a frame from 8-bit palette indices, treating `transparent`
+    /// as transparent.
+    ///
+    /// Fails with `SlpError::BadDimensions` if `data` holds fewer than
+    /// `width * height` pixels.
+    pub fn from_indexed(width: u32, height: u32, data: &[u8], transparent: u8) -> Result<SlpFrame, SlpError> {
+        let len = try!(pixel_count(width, height, data.len()));
+        let pixels = data[..len].iter().map(|&idx| {
+            if idx == transparent {
+                SlpPixel::Transparent
+            } else {
+                SlpPixel::Color(idx)
+            }
+        }).collect::<Vec<_>>();
+
+        Ok(SlpFrame {
+            width: width,
+            height: height,
+            hotspot: (0, 0),
+            pixels: pixels.into_boxed_slice()
+        })
+    }
+
+    /// Creates a frame from 32-bit RGBA, mapping each color to the closest
+    /// color of `pal`. Pixels with an alpha below 128 are transparent.
+    ///
+    /// Fails with `SlpError::BadDimensions` if `data` holds fewer than
+    /// `width * height` pixels.
+    pub fn from_rgba(width: u32, height: u32, data: &[u8], pal: &Palette) -> Result<SlpFrame, SlpError> {
+        let len = try!(pixel_count(width, height, data.len() / 4));
+        let mut cache = HashMap::new();
+
+        let pixels = data[..len * 4].chunks(4).map(|px| {
+            if px[3] < 0x80 {
+                return SlpPixel::Transparent;
+            }
+
+            let key = (px[0], px[1], px[2]);
+            let idx = *cache.entry(key).or_insert_with(|| closest_color(pal, key));
+
+            SlpPixel::Color(idx)
+        }).collect::<Vec<_>>();
+
+        Ok(SlpFrame {
+            width: width,
+            height: height,
+            hotspot: (0, 0),
+            pixels: pixels.into_boxed_slice()
+        })
+    }
+
+    pub fn with_hotspot(mut self, x: i32, y: i32) -> SlpFrame {
+        self.hotspot = (x, y);
+        self
+    }
+
+    /// Turns the pixels covered by `mask` into player color pixels, taking
+    /// their index relative to the palette band starting at `player_base`.
+    ///
+    /// Fails with `SlpError::PaletteIndex` if a masked color lies outside
+    /// the 8 colors of the band.
+    pub fn with_player_mask(mut self, mask: &[u8], player_base: usize) -> Result<SlpFrame, SlpError> {
+        for (px, &m) in self.pixels.iter_mut().zip(mask.iter()) {
+            if m != 0 {
+                if let SlpPixel::Color(idx) = *px {
+                    let idx = idx as usize;
+                    if idx < player_base || idx >= player_base + PLAYER_BAND_SIZE {
+                        return Err(SlpError::PaletteIndex(idx));
+                    }
+
+                    *px = SlpPixel::Player((idx - player_base) as u8);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Turns the pixels covered by `mask` into shadow pixels.
+    pub fn with_shadow_mask(mut self, mask: &[u8]) -> SlpFrame {
+        for (px, &m) in self.pixels.iter_mut().zip(mask.iter()) {
+            if m != 0 {
+                *px = SlpPixel::Shadow;
+            }
+        }
+
+        self
+    }
+
+    /// Turns the pixels covered by an outline mask, as produced by
+    /// `SlpLayeredRasterizer`, into outline pixels.
+    pub fn with_outline_mask(mut self, mask: &[u8]) -> SlpFrame {
+        for (px, &m) in self.pixels.iter_mut().zip(mask.iter()) {
+            match m {
+                OUTLINE_PLAYER => *px = SlpPixel::PlayerOutline,
+                OUTLINE_BLACK => *px = SlpPixel::BlackOutline,
+                _ => {}
+            }
+        }
+
+        self
+    }
+}
+
+/// Encodes frames as an SLP 2.0N file.
+pub struct SlpWriter {
+    comment: [u8; 24],
+    frames: Vec<SlpFrame>
+}
+
+/// Fills shorter than this are written as part of a color list instead.
+const MIN_FILL: usize = 3;
+
+const SLP_HEADER_SIZE: u32 = 32;
+const SLP_ENTRY_SIZE: u32 = 32;
+
+impl SlpWriter {
+    pub fn new() -> SlpWriter {
+        let mut comment = [0u8; 24];
+        let text = b"ArtDesk 1.00 SLP Writer";
+        comment[..text.len()].copy_from_slice(text);
+
+        SlpWriter {
+            comment: comment,
+            frames: Vec::new()
+        }
+    }
+
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        let len = ::std::cmp::min(comment.len(), 24);
+        self.comment = [0u8; 24];
+        self.comment[..len].copy_from_slice(&comment[..len]);
+    }
+
+    pub fn add_frame(&mut self, frame: SlpFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Encodes all frames.
+    ///
+    /// Fails with `SlpError::BadDimensions` if a frame is larger than an SLP
+    /// can describe or its pixels don't match its size, and with
+    /// `SlpError::BadOffset` if the file would grow past 4 GiB.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), SlpError> {
+        let mut encoded = Vec::new();
+        let mut offset = SLP_HEADER_SIZE as u64 + self.frames.len() as u64 * SLP_ENTRY_SIZE as u64;
+
+        for frame in self.frames.iter() {
+            if try!(pixel_count(frame.width, frame.height, frame.pixels.len())) != frame.pixels.len() {
+                return Err(SlpError::BadDimensions(frame.width as i32, frame.height as i32));
+            }
+
+            let (skips, rows) = encode_frame(frame);
+
+            let outline_offset = offset;
+            let cmd_offset = outline_offset + frame.height as u64 * 4;
+            let mut row_offset = cmd_offset + frame.height as u64 * 4;
+
+            let mut offsets = Vec::with_capacity(rows.len());
+            for row in rows.iter() {
+                offsets.push(row_offset as u32);
+                row_offset += row.len() as u64;
+            }
+
+            if row_offset > ::std::u32::MAX as u64 {
+                return Err(SlpError::BadOffset(::std::u32::MAX));
+            }
+
+            encoded.push((outline_offset as u32, cmd_offset as u32, skips, offsets, rows));
+            offset = row_offset;
+        }
+
+        try!(writer.write_all(b"2.0N"));
+        try!(writer.write_u32::<LittleEndian>(self.frames.len() as _));
+        try!(writer.write_all(&self.comment));
+
+        for (frame, &(outline_offset, cmd_offset, _, _, _)) in self.frames.iter().zip(encoded.iter()) {
+            try!(writer.write_u32::<LittleEndian>(cmd_offset));
+            try!(writer.write_u32::<LittleEndian>(outline_offset));
+            try!(writer.write_u32::<LittleEndian>(0));
+            try!(writer.write_u32::<LittleEndian>(0));
+            try!(writer.write_i32::<LittleEndian>(frame.width as _));
+            try!(writer.write_i32::<LittleEndian>(frame.height as _));
+            try!(writer.write_i32::<LittleEndian>(frame.hotspot.0));
+            try!(writer.write_i32::<LittleEndian>(frame.hotspot.1));
+        }
+
+        for &(_, _, ref skips, ref offsets, ref rows) in encoded.iter() {
+            for &(left, right) in skips.iter() {
+                try!(writer.write_u16::<LittleEndian>(left));
+                try!(writer.write_u16::<LittleEndian>(right));
+            }
+
+            for &offset in offsets.iter() {
+                try!(writer.write_u32::<LittleEndian>(offset));
+            }
+
+            for row in rows.iter() {
+                try!(writer.write_all(row));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of pixels of a `width` by `height` frame, checking that the
+/// frame is no larger than `MAX_SIZE` and that `available` pixels are
+/// enough to fill it.
+fn pixel_count(width: u32, height: u32, available: usize) -> Result<usize, SlpError> {
+    match (width as usize).checked_mul(height as usize) {
+        Some(len) if width <= MAX_SIZE && height <= MAX_SIZE && len <= available => Ok(len),
+        _ => Err(SlpError::BadDimensions(width as i32, height as i32))
+    }
+}
+
+fn closest_color(pal: &Palette, (r, g, b): (u8, u8, u8)) -> u8 {
+    let mut best = (0, ::std::i32::MAX);
+
+    for (idx, &(pr, pg, pb)) in pal.colors.iter().enumerate().take(256) {
+        let (dr, dg, db) = (pr as i32 - r as i32, pg as i32 - g as i32, pb as i32 - b as i32);
+        let dist = dr * dr + dg * dg + db * db;
+
+        if dist < best.1 {
+            best = (idx, dist);
+        }
+    }
+
+    best.0 as u8
+}
+
+/// Encodes the outline table and command rows of a frame.
+fn encode_frame(frame: &SlpFrame) -> (Vec<(u16, u16)>, Vec<Vec<u8>>) {
+    let width = frame.width as usize;
+    let mut skips = Vec::with_capacity(frame.height as _);
+    let mut rows = Vec::with_capacity(frame.height as _);
+
+    for y in 0..frame.height as usize {
+        let row = &frame.pixels[y * width..(y + 1) * width];
+        let first = row.iter().position(|&px| px != SlpPixel::Transparent);
+        let last = row.iter().rposition(|&px| px != SlpPixel::Transparent);
+
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                skips.push((0x8000, 0x8000));
+                rows.push(vec![0x0f]);
+                continue;
+            }
+        };
+
+        skips.push((first as u16, (width - 1 - last) as u16));
+
+        let mut out = Vec::new();
+        let pixels = &row[first..last + 1];
+        let mut x = 0;
+        while x < pixels.len() {
+            let px = pixels[x];
+            let run = pixels[x..].iter().take_while(|&&p| p == px).count();
+
+            match px {
+                SlpPixel::Transparent => {
+                    write_skip(&mut out, run);
+                    x += run;
+                },
+                SlpPixel::Color(idx) if run >= MIN_FILL => {
+                    write_long(&mut out, 0x07, run, &[idx]);
+                    x += run;
+                },
+                SlpPixel::Color(_) => {
+                    // Gather colors up to the next transparent pixel, other
+                    // kind of pixel or run long enough to be filled.
+                    let mut end = x;
+                    while end < pixels.len() {
+                        match pixels[end] {
+                            SlpPixel::Color(idx) => {
+                                let run = pixels[end..].iter().take_while(|&&p| p == SlpPixel::Color(idx)).count();
+                                if run >= MIN_FILL {
+                                    break;
+                                }
+                                end += run;
+                            },
+                            _ => break
+                        }
+                    }
+
+                    let colors = pixels[x..end].iter().map(|&px| match px {
+                        SlpPixel::Color(idx) => idx,
+                        _ => unreachable!()
+                    }).collect::<Vec<_>>();
+                    write_colors(&mut out, &colors);
+                    x = end;
+                },
+                SlpPixel::Player(idx) if run >= MIN_FILL => {
+                    write_long(&mut out, 0x0a, run, &[idx]);
+                    x += run;
+                },
+                SlpPixel::Player(_) => {
+                    let colors = pixels[x..].iter().take_while(|&&p| match p {
+                        SlpPixel::Player(_) => true,
+                        _ => false
+                    }).map(|&px| match px {
+                        SlpPixel::Player(idx) => idx,
+                        _ => unreachable!()
+                    }).collect::<Vec<_>>();
+
+                    for chunk in colors.chunks(255) {
+                        write_long(&mut out, 0x06, chunk.len(), &[]);
+                        out.extend_from_slice(chunk);
+                    }
+                    x += colors.len();
+                },
+                SlpPixel::Shadow => {
+                    write_long(&mut out, 0x0b, run, &[]);
+                    x += run;
+                },
+                SlpPixel::PlayerOutline | SlpPixel::BlackOutline => {
+                    let (single, span) = if px == SlpPixel::PlayerOutline {
+                        (0x4e, 0x5e)
+                    } else {
+                        (0x6e, 0x7e)
+                    };
+
+                    let mut left = run;
+                    while left > 0 {
+                        let len = ::std::cmp::min(left, 255);
+                        if len == 1 {
+                            out.push(single);
+                        } else {
+                            out.push(span);
+                            out.push(len as u8);
+                        }
+                        left -= len;
+                    }
+                    x += run;
+                }
+            }
+        }
+
+        out.push(0x0f);
+        rows.push(out);
+    }
+
+    (skips, rows)
+}
+
+fn write_skip(out: &mut Vec<u8>, mut len: usize) {
+    while len > 0 {
+        let n = ::std::cmp::min(len, 0xfff);
+        if n < 64 {
+            out.push((n << 2) as u8 | 0x01);
+        } else {
+            out.push(((n & 0xf00) >> 4) as u8 | 0x03);
+            out.push((n & 0xff) as u8);
+        }
+        len -= n;
+    }
+}
+
+fn write_colors(out: &mut Vec<u8>, colors: &[u8]) {
+    for chunk in colors.chunks(0xfff) {
+        let n = chunk.len();
+        if n < 64 {
+            out.push((n << 2) as u8);
+        } else {
+            out.push(((n & 0xf00) >> 4) as u8 | 0x02);
+            out.push((n & 0xff) as u8);
+        }
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Writes a command whose length is stored in the upper nibble, or in the
+/// following byte if it doesn't fit, splitting runs longer than 255.
+fn write_long(out: &mut Vec<u8>, command: u8, mut len: usize, data: &[u8]) {
+    while len > 0 {
+        let n = ::std::cmp::min(len, 255);
+        if n < 16 {
+            out.push((n << 4) as u8 | command);
+        } else {
+            out.push(command);
+            out.push(n as u8);
+        }
+        out.extend_from_slice(data);
+        len -= n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use format::pal::Palette;
+    use format::slp::{SlpReader, SlpLayeredRasterizer, SlpError};
+    use format::slp::layers::{OUTLINE_PLAYER, OUTLINE_BLACK};
+    use super::*;
+
+    /// Pixels covering every kind of command, including runs long enough
+    /// to need long lengths and splitting.
+    fn pixels(width: usize, height: usize) -> Vec<SlpPixel> {
+        let mut seed = 1u32;
+
+        (0..width * height).map(|i| {
+            let (x, y) = (i % width, i / width);
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+
+            match y {
+                0 => SlpPixel::Transparent,
+                1 => SlpPixel::Color(7),
+                2 if x < 3 => SlpPixel::Transparent,
+                2 => SlpPixel::Player(3),
+                3 => SlpPixel::Shadow,
+                4 => if x % 2 == 0 { SlpPixel::PlayerOutline } else { SlpPixel::BlackOutline },
+                _ => match (seed >> 16) % 7 {
+                    0 => SlpPixel::Transparent,
+                    1 => SlpPixel::Color((seed >> 8) as u8),
+                    2 => SlpPixel::Color(9),
+                    3 => SlpPixel::Player((seed >> 8) as u8 % 8),
+                    4 => SlpPixel::Shadow,
+                    5 => SlpPixel::PlayerOutline,
+                    _ => SlpPixel::BlackOutline
+                }
+            }
+        }).collect()
+    }
+
+    fn write(frames: Vec<SlpFrame>) -> Result<Vec<u8>, SlpError> {
+        let mut writer = SlpWriter::new();
+        for frame in frames {
+            writer.add_frame(frame);
+        }
+
+        let mut out = Vec::new();
+        try!(writer.write(&mut out));
+        Ok(out)
+    }
+
+    #[test]
+    fn round_trip() {
+        // Palette whose red channel is the color's index.
+        let pal = Palette {
+            colors: (0..256).map(|i| (i as u8, 0, 0)).collect::<Vec<_>>().into_boxed_slice()
+        };
+
+        for &(width, height) in [(1, 1), (5, 9), (300, 8), (4200, 6)].iter() {
+            let pixels = pixels(width, height);
+            let frame = SlpFrame {
+                width: width as u32,
+                height: height as u32,
+                hotspot: (0, 0),
+                pixels: pixels.clone().into_boxed_slice()
+            }.with_hotspot(3, -2);
+
+            let data = write(vec![frame]).unwrap();
+            let mut reader = SlpReader::new(Cursor::new(data)).unwrap();
+            assert_eq!(reader.comment(), "ArtDesk 1.00 SLP Writer");
+
+            let obj = reader.read_shape(0).unwrap();
+            assert_eq!((obj.width, obj.height, obj.hotspot()), (width as i32, height as i32, (3, -2)));
+
+            let layers = SlpLayeredRasterizer::rasterize(&obj, &pal).unwrap();
+            let decoded = (0..width * height).map(|i| {
+                if layers.player_mask[i] != 0 {
+                    SlpPixel::Player(layers.player[i])
+                } else if layers.shadow[i] != 0 {
+                    SlpPixel::Shadow
+                } else if layers.outline[i] == OUTLINE_PLAYER {
+                    SlpPixel::PlayerOutline
+                } else if layers.outline[i] == OUTLINE_BLACK {
+                    SlpPixel::BlackOutline
+                } else if layers.color[i * 4 + 3] != 0 {
+                    SlpPixel::Color(layers.color[i * 4])
+                } else {
+                    SlpPixel::Transparent
+                }
+            }).collect::<Vec<_>>();
+
+            assert!(decoded == pixels, "frame {}x{} differs", width, height);
+        }
+    }
+
+    #[test]
+    fn empty_frames() {
+        let frames = vec![
+            SlpFrame::from_indexed(0, 4, &[], 0).unwrap(),
+            SlpFrame::from_indexed(4, 0, &[], 0).unwrap(),
+            SlpFrame::from_indexed(2, 2, &[0; 4], 0).unwrap()
+        ];
+
+        let data = write(frames).unwrap();
+        let mut reader = SlpReader::new(Cursor::new(data)).unwrap();
+        for i in 0..3 {
+            assert!(reader.read_shape(i).unwrap().spans().unwrap().iter().all(|row| row.is_empty()));
+        }
+    }
+
+    #[test]
+    fn player_mask() {
+        let frame = SlpFrame::from_indexed(4, 1, &[16, 23, 5, 0], 0).unwrap();
+
+        let masked = frame.clone().with_player_mask(&[1, 1, 0, 1], 16).unwrap();
+        assert_eq!(&masked.pixels[..], &[SlpPixel::Player(0), SlpPixel::Player(7), SlpPixel::Color(5), SlpPixel::Transparent]);
+
+        for &(mask, base, idx) in &[([0, 0, 1, 0], 16, 5), ([1, 0, 0, 0], 17, 16), ([0, 1, 0, 0], 8, 23)] {
+            match frame.clone().with_player_mask(&mask, base) {
+                Err(SlpError::PaletteIndex(i)) => assert_eq!(i, idx),
+                other => panic!("{:?}", other.map(|_| ()))
+            }
+        }
+    }
+
+    #[test]
+    fn bad_frames() {
+        let pal = Palette {
+            colors: vec![(0, 0, 0); 4].into_boxed_slice()
+        };
+
+        assert!(SlpFrame::from_indexed(3, 3, &[1; 8], 0).is_err());
+        assert!(SlpFrame::from_rgba(2, 2, &[0xff; 15], &pal).is_err());
+        assert!(SlpFrame::from_indexed(0x8000, 1, &[1; 0x8000], 0).is_err());
+
+        let short = SlpFrame {
+            width: 4,
+            height: 4,
+            hotspot: (0, 0),
+            pixels: vec![SlpPixel::Color(1); 15].into_boxed_slice()
+        };
+        match write(vec![short]) {
+            Err(SlpError::BadDimensions(4, 4)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Intermediate sprites from Age of Empires II: Definitive Edition, which
+//! are converted to SMX for the game.
+//!
+//! SMP frames hold the same layers as SMX frames but keep SLP-style row
+//! offset tables, and store each pixel inline after its draw command as 4
+//! bytes: palette index, palette section and two bytes of damage modifiers.
+
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use format::smx::{SmxFrame, SmxLayer, SmxLayerKind, SmxPixel, layer_len, frame_not_found, row_overflow};
+
+const LAYER_MAIN: u32 = 0x02;
+const LAYER_SHADOW: u32 = 0x04;
+const LAYER_OUTLINE: u32 = 0x08;
+
+pub struct SmpReader<R: Read + Seek> {
+    reader: R,
+    frames: Vec<u32>
+}
+
+impl<R: Read + Seek> SmpReader<R> {
+    pub fn new(mut reader: R) -> Result<SmpReader<R>, ::std::io::Error> {
+        let mut signature = [0u8; 4];
+        try!(reader.read_exact(&mut signature));
+        if &signature != b"SMP$" {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "not an SMP file"));
+        }
+
+        let _version = try!(reader.read_u32::<LittleEndian>());
+        let count = try!(reader.read_u32::<LittleEndian>());
+        let _animations = try!(reader.read_u32::<LittleEndian>());
+        let _frames_per_animation = try!(reader.read_u32::<LittleEndian>());
+        let _checksum = try!(reader.read_u32::<LittleEndian>());
+        let _size = try!(reader.read_u32::<LittleEndian>());
+        let _source_format = try!(reader.read_u32::<LittleEndian>());
+        let mut comment = [0u8; 32];
+        try!(reader.read_exact(&mut comment));
+
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            frames.push(try!(reader.read_u32::<LittleEndian>()));
+        }
+
+        Ok(SmpReader {
+            reader: reader,
+            frames: frames
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn read_frame(&mut self, index: usize) -> Result<SmxFrame, ::std::io::Error> {
+        let start = try!(self.frames.get(index).cloned().ok_or_else(|| frame_not_found(index))) as u64;
+        try!(self.reader.seek(SeekFrom::Start(start)));
+
+        let _width = try!(self.reader.read_u32::<LittleEndian>());
+        let _height = try!(self.reader.read_u32::<LittleEndian>());
+        let _hotspot_x = try!(self.reader.read_i32::<LittleEndian>());
+        let _hotspot_y = try!(self.reader.read_i32::<LittleEndian>());
+        let _ty = try!(self.reader.read_u32::<LittleEndian>());
+        let palette = try!(self.reader.read_u32::<LittleEndian>());
+        let layer_count = try!(self.reader.read_u32::<LittleEndian>());
+
+        let mut infos = Vec::new();
+        for _ in 0..layer_count {
+            let width = try!(self.reader.read_u32::<LittleEndian>());
+            let height = try!(self.reader.read_u32::<LittleEndian>());
+            let hotspot_x = try!(self.reader.read_i32::<LittleEndian>());
+            let hotspot_y = try!(self.reader.read_i32::<LittleEndian>());
+            let ty = try!(self.reader.read_u32::<LittleEndian>());
+            let outline_offset = try!(self.reader.read_u32::<LittleEndian>());
+            let cmd_offset = try!(self.reader.read_u32::<LittleEndian>());
+            let _flags = try!(self.reader.read_u32::<LittleEndian>());
+
+            infos.push((width, height, (hotspot_x, hotspot_y), ty, outline_offset, cmd_offset));
+        }
+
+        let mut layers = Vec::new();
+        for &(width, height, hotspot, ty, outline_offset, cmd_offset) in infos.iter() {
+            let kind = match ty {
+                LAYER_MAIN => SmxLayerKind::Main,
+                LAYER_SHADOW => SmxLayerKind::Shadow,
+                LAYER_OUTLINE => SmxLayerKind::Outline,
+                _ => continue
+            };
+
+            // Offsets are relative to the start of the frame.
+            try!(self.reader.seek(SeekFrom::Start(start + outline_offset as u64)));
+            let len = try!(layer_len(width, height));
+
+            let mut edges = Vec::new();
+            for _ in 0..height {
+                let left = try!(self.reader.read_u16::<LittleEndian>());
+                let right = try!(self.reader.read_u16::<LittleEndian>());
+                edges.push((left, right));
+            }
+
+            try!(self.reader.seek(SeekFrom::Start(start + cmd_offset as u64)));
+            let mut rows = Vec::new();
+            for _ in 0..height {
+                rows.push(try!(self.reader.read_u32::<LittleEndian>()));
+            }
+
+            let mut pixels = vec![SmxPixel::Transparent; len];
+            for y in 0..height as usize {
+                let (left, right) = edges[y];
+                if left == 0xffff || right == 0xffff {
+                    continue;
+                }
+
+                try!(self.reader.seek(SeekFrom::Start(start + rows[y] as u64)));
+                let row = y * width as usize;
+                let mut x = left as usize;
+
+                loop {
+                    let cmd = try!(self.reader.read_u8());
+                    let count = (cmd >> 2) as usize + 1;
+
+                    match cmd & 0x03 {
+                        0 => {
+                            x += count;
+                        },
+                        3 => break,
+                        op => {
+                            for _ in 0..count {
+                                let px = match kind {
+                                    SmxLayerKind::Outline => SmxPixel::Outline,
+                                    _ => {
+                                        let mut data = [0u8; 4];
+                                        try!(self.reader.read_exact(&mut data));
+
+                                        if kind == SmxLayerKind::Shadow {
+                                            SmxPixel::Shadow(data[0])
+                                        } else if op == 2 {
+                                            SmxPixel::Player(data[0] as u16)
+                                        } else {
+                                            SmxPixel::Color(((data[1] as u16 & 0x03) << 8) | data[0] as u16)
+                                        }
+                                    }
+                                };
+
+                                if x >= width as usize {
+                                    return Err(row_overflow(y));
+                                }
+                                pixels[row + x] = px;
+                                x += 1;
+                            }
+                        }
+                    }
+                }
+            }
+
+            layers.push(SmxLayer {
+                kind: kind,
+                width: width,
+                height: height,
+                hotspot: hotspot,
+                pixels: pixels.into_boxed_slice()
+            });
+        }
+
+        Ok(SmxFrame {
+            palette: palette as _,
+            layers: layers
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use format::smx::{SmxLayerKind, SmxPixel};
+    use super::*;
+
+    /// An SMP file with one frame holding `layers`, each as type, width,
+    /// height, row edges and the commands of each row, pixel data included.
+    fn smp(layers: &[(u32, u32, u32, Vec<(u16, u16)>, Vec<Vec<u8>>)]) -> Vec<u8> {
+        let mut data = b"SMP$".to_vec();
+        for &value in &[0x0b, 1, 1, 1, 0, 0, 0] {
+            data.write_u32::<LittleEndian>(value).unwrap();
+        }
+        data.extend_from_slice(&[0; 32]);
+        let start = data.len() as u32 + 4;
+        data.write_u32::<LittleEndian>(start).unwrap();
+
+        // Tables and rows follow the frame and layer headers, with offsets
+        // relative to the start of the frame.
+        let mut frame = Vec::new();
+        let mut body = Vec::new();
+        let header_len = 28 + 32 * layers.len() as u32;
+        for &value in &[0, 0, 0, 0, 0, 3, layers.len() as u32] {
+            frame.write_u32::<LittleEndian>(value).unwrap();
+        }
+
+        for &(ty, width, height, ref edges, ref rows) in layers {
+            let outline_offset = header_len + body.len() as u32;
+            for &(left, right) in edges {
+                body.write_u16::<LittleEndian>(left).unwrap();
+                body.write_u16::<LittleEndian>(right).unwrap();
+            }
+
+            let cmd_offset = header_len + body.len() as u32;
+            let mut row_offset = cmd_offset + 4 * rows.len() as u32;
+            for row in rows {
+                body.write_u32::<LittleEndian>(row_offset).unwrap();
+                row_offset += row.len() as u32;
+            }
+            for row in rows {
+                body.extend_from_slice(row);
+            }
+
+            for &value in &[width, height, 0, 0, ty, outline_offset, cmd_offset, 0] {
+                frame.write_u32::<LittleEndian>(value).unwrap();
+            }
+        }
+
+        data.extend_from_slice(&frame);
+        data.extend_from_slice(&body);
+        data
+    }
+
+    fn read(data: Vec<u8>) -> Result<SmxFrame, ::std::io::Error> {
+        let mut reader = try!(SmpReader::new(Cursor::new(data)));
+        assert_eq!(reader.len(), 1);
+        reader.read_frame(0)
+    }
+
+    #[test]
+    fn read_layers() {
+        let main = (LAYER_MAIN, 3, 2, vec![(0, 0), (1, 0)], vec![
+            // A color with palette section 1, a player color, then the end.
+            vec![0x01, 0x05, 0x01, 0x00, 0x00, 0x02, 0x07, 0x00, 0x00, 0x00, 0x03],
+            // Two colors after the left margin.
+            vec![0x05, 0xff, 0x02, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x03]
+        ]);
+        let shadow = (LAYER_SHADOW, 2, 1, vec![(0, 0)], vec![vec![0x00, 0x01, 0x40, 0, 0, 0, 0x03]]);
+        let outline = (LAYER_OUTLINE, 2, 2, vec![(0xffff, 0xffff), (0, 0)], vec![vec![], vec![0x05, 0x03]]);
+        let unknown = (0x10, 1, 1, vec![(0, 0)], vec![vec![0x03]]);
+
+        let frame = read(smp(&[main, shadow, outline, unknown])).unwrap();
+        assert_eq!(frame.palette, 3);
+        assert_eq!(frame.layers.len(), 3);
+
+        assert_eq!(&frame.layer(SmxLayerKind::Main).unwrap().pixels[..], &[
+            SmxPixel::Color(0x105), SmxPixel::Player(7), SmxPixel::Transparent,
+            SmxPixel::Transparent, SmxPixel::Color(0x2ff), SmxPixel::Color(0x010)
+        ]);
+        assert_eq!(&frame.layer(SmxLayerKind::Shadow).unwrap().pixels[..], &[SmxPixel::Transparent, SmxPixel::Shadow(0x40)]);
+        assert_eq!(&frame.layer(SmxLayerKind::Outline).unwrap().pixels[..], &[
+            SmxPixel::Transparent, SmxPixel::Transparent, SmxPixel::Outline, SmxPixel::Outline
+        ]);
+    }
+
+    #[test]
+    fn read_bad_layers() {
+        // Two pixels in a row one pixel wide.
+        let data = smp(&[(LAYER_OUTLINE, 1, 1, vec![(0, 0)], vec![vec![0x05, 0x03]])]);
+        assert_eq!(read(data).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let data = smp(&[(LAYER_OUTLINE, 0x10000, 1, vec![(0, 0)], vec![vec![0x03]])]);
+        assert_eq!(read(data).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Pixel data cut short.
+        let data = smp(&[(LAYER_MAIN, 1, 1, vec![(0, 0)], vec![vec![0x01, 0x05]])]);
+        assert_eq!(read(data).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        let mut reader = SmpReader::new(Cursor::new(smp(&[]))).unwrap();
+        assert_eq!(reader.read_frame(1).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
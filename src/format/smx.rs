@@ -0,0 +1,602 @@
+//! Sprites from Age of Empires II: Definitive Edition.
+//!
+//! An SMX frame has up to three layers: the main graphic, its shadow and
+//! the outline shown when the unit is hidden behind a building. Each layer
+//! has its own size and hotspot. Main layer pixels are packed separately
+//! from the commands, either as "4plus1" (4 palette indices followed by a
+//! byte holding the palette section of each) or "8to5" (2 pixels of 20 bits
+//! in 5 bytes, the extra bits being damage modifiers).
+
+use std::io::{Read, Seek, SeekFrom, Cursor};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use format::pal::Palette;
+use format::slp::SlpError;
+use format::slp::layers::{SlpLayers, OUTLINE_PLAYER};
+
+/// Largest layer width or height, which SMX stores in 16 bits.
+pub const MAX_LAYER_SIZE: u32 = 0xffff;
+
+/// A decoded pixel of an SMX or SMP layer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SmxPixel {
+    Transparent,
+    /// Palette index, including the palette section in the upper bits.
+    Color(u16),
+    /// Index into the player's colors.
+    Player(u16),
+    /// Shadow intensity.
+    Shadow(u8),
+    Outline
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SmxLayerKind {
+    Main,
+    Shadow,
+    Outline
+}
+
+#[derive(Debug)]
+pub struct SmxLayer {
+    pub kind: SmxLayerKind,
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (i32, i32),
+    pub pixels: Box<[SmxPixel]>
+}
+
+#[derive(Debug)]
+pub struct SmxFrame {
+    /// Palette the main layer's colors index into.
+    pub palette: u8,
+    pub layers: Vec<SmxLayer>
+}
+
+impl SmxFrame {
+    pub fn layer(&self, kind: SmxLayerKind) -> Option<&SmxLayer> {
+        self.layers.iter().find(|layer| layer.kind == kind)
+    }
+
+    /// Size and hotspot of the canvas covering all layers, aligned on their
+    /// hotspots, or `None` if the layers are too far apart for its size to
+    /// fit in a `u32`.
+    pub fn bounds(&self) -> Option<(u32, u32, (i32, i32))> {
+        let left = self.layers.iter().map(|l| l.hotspot.0).max().unwrap_or(0);
+        let top = self.layers.iter().map(|l| l.hotspot.1).max().unwrap_or(0);
+        let right = self.layers.iter().map(|l| l.width as i64 - l.hotspot.0 as i64).max().unwrap_or(0);
+        let bottom = self.layers.iter().map(|l| l.height as i64 - l.hotspot.1 as i64).max().unwrap_or(0);
+
+        let width = left as i64 + right;
+        let height = top as i64 + bottom;
+        if width > u32::max_value() as i64 || height > u32::max_value() as i64 {
+            return None;
+        }
+
+        Some((width as u32, height as u32, (left, top)))
+    }
+}
+
+/// Number of pixels in a `width` by `height` layer, checking that it is no
+/// larger than SMX allows.
+pub fn layer_len(width: u32, height: u32) -> Result<usize, ::std::io::Error> {
+    if width > MAX_LAYER_SIZE || height > MAX_LAYER_SIZE {
+        return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "SMX layer is too large"));
+    }
+
+    (width as usize).checked_mul(height as usize)
+        .ok_or_else(|| ::std::io::Error::new(::std::io::ErrorKind::InvalidData, "SMX layer is too large"))
+}
+
+const FRAME_MAIN: u8 = 0x01;
+const FRAME_SHADOW: u8 = 0x02;
+const FRAME_OUTLINE: u8 = 0x04;
+const FRAME_8TO5: u8 = 0x08;
+
+pub struct SmxReader<R: Read + Seek> {
+    reader: R,
+    frames: Vec<u64>,
+    /// Length of the stream, which layer data must fit in.
+    end: u64
+}
+
+impl<R: Read + Seek> SmxReader<R> {
+    pub fn new(mut reader: R) -> Result<SmxReader<R>, ::std::io::Error> {
+        let mut signature = [0u8; 4];
+        try!(reader.read_exact(&mut signature));
+        if &signature != b"SMPX" {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "not an SMX file"));
+        }
+
+        let _version = try!(reader.read_u16::<LittleEndian>());
+        let count = try!(reader.read_u16::<LittleEndian>());
+        let _size = try!(reader.read_u32::<LittleEndian>());
+        let _uncompressed_size = try!(reader.read_u32::<LittleEndian>());
+        let mut comment = [0u8; 16];
+        try!(reader.read_exact(&mut comment));
+
+        // Frames are stored back to back, so find where each one starts.
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            let start = try!(reader.seek(SeekFrom::Current(0)));
+            frames.push(start);
+
+            let ty = try!(reader.read_u8());
+            let _palette = try!(reader.read_u8());
+            let _uncompressed_size = try!(reader.read_u32::<LittleEndian>());
+
+            for &bit in &[FRAME_MAIN, FRAME_SHADOW, FRAME_OUTLINE] {
+                if ty & bit != 0 {
+                    try!(reader.seek(SeekFrom::Current(8)));
+                    let len = try!(reader.read_u32::<LittleEndian>());
+                    try!(reader.seek(SeekFrom::Current(4 + len as i64)));
+                }
+            }
+        }
+
+        let end = try!(reader.seek(SeekFrom::End(0)));
+
+        Ok(SmxReader {
+            reader: reader,
+            frames: frames,
+            end: end
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn read_frame(&mut self, index: usize) -> Result<SmxFrame, ::std::io::Error> {
+        let start = try!(self.frames.get(index).cloned().ok_or_else(|| frame_not_found(index)));
+        try!(self.reader.seek(SeekFrom::Start(start)));
+
+        let ty = try!(self.reader.read_u8());
+        let palette = try!(self.reader.read_u8());
+        let _uncompressed_size = try!(self.reader.read_u32::<LittleEndian>());
+
+        let mut layers = Vec::new();
+        for &(bit, kind) in &[(FRAME_MAIN, SmxLayerKind::Main), (FRAME_SHADOW, SmxLayerKind::Shadow), (FRAME_OUTLINE, SmxLayerKind::Outline)] {
+            if ty & bit == 0 {
+                continue;
+            }
+
+            let width = try!(self.reader.read_u16::<LittleEndian>()) as u32;
+            let height = try!(self.reader.read_u16::<LittleEndian>()) as u32;
+            let hotspot_x = try!(self.reader.read_i16::<LittleEndian>()) as i32;
+            let hotspot_y = try!(self.reader.read_i16::<LittleEndian>()) as i32;
+            let len = try!(self.reader.read_u32::<LittleEndian>());
+            let _unknown = try!(self.reader.read_u32::<LittleEndian>());
+
+            // Check before allocating, so a bogus length can't claim more
+            // memory than the file has.
+            let pos = try!(self.reader.seek(SeekFrom::Current(0)));
+            if pos + len as u64 > self.end {
+                return Err(truncated());
+            }
+
+            let mut data = vec![0u8; len as usize];
+            try!(self.reader.read_exact(&mut data));
+
+            let pixels = try!(decode_layer(&data, kind, width, height, ty & FRAME_8TO5 != 0));
+
+            layers.push(SmxLayer {
+                kind: kind,
+                width: width,
+                height: height,
+                hotspot: (hotspot_x, hotspot_y),
+                pixels: pixels
+            });
+        }
+
+        Ok(SmxFrame {
+            palette: palette,
+            layers: layers
+        })
+    }
+}
+
+fn decode_layer(data: &[u8], kind: SmxLayerKind, width: u32, height: u32, packed_8to5: bool) -> Result<Box<[SmxPixel]>, ::std::io::Error> {
+    let mut reader = Cursor::new(data);
+
+    let len = try!(layer_len(width, height));
+
+    let mut edges = Vec::new();
+    for _ in 0..height {
+        let left = try!(reader.read_u16::<LittleEndian>());
+        let right = try!(reader.read_u16::<LittleEndian>());
+        edges.push((left, right));
+    }
+
+    let commands_len = try!(reader.read_u32::<LittleEndian>()) as usize;
+    let start = reader.position() as usize;
+    let end = try!(start.checked_add(commands_len).ok_or_else(truncated));
+    let commands = try!(data.get(start..end).ok_or_else(truncated));
+
+    // Only the main layer keeps its pixels apart from the commands.
+    let pixel_data = if kind == SmxLayerKind::Main {
+        reader.set_position(end as u64);
+        let len = try!(reader.read_u32::<LittleEndian>()) as usize;
+        let start = reader.position() as usize;
+        let end = try!(start.checked_add(len).ok_or_else(truncated));
+        try!(data.get(start..end).ok_or_else(truncated))
+    } else {
+        &[][..]
+    };
+
+    let mut pixels = vec![SmxPixel::Transparent; len];
+    let mut commands = Cursor::new(commands);
+    let mut packed = 0;
+
+    for (y, &(left, _)) in edges.iter().enumerate() {
+        if left == 0xffff {
+            continue;
+        }
+
+        let row = y * width as usize;
+        let mut x = left as usize;
+
+        loop {
+            let cmd = try!(commands.read_u8());
+            let count = (cmd >> 2) as usize + 1;
+
+            match cmd & 0x03 {
+                0 => {
+                    x += count;
+                },
+                3 => break,
+                op => {
+                    for _ in 0..count {
+                        let px = match kind {
+                            SmxLayerKind::Main => {
+                                let idx = try!(unpack(pixel_data, packed, packed_8to5).ok_or_else(truncated));
+                                packed += 1;
+
+                                if op == 2 {
+                                    SmxPixel::Player(idx)
+                                } else {
+                                    SmxPixel::Color(idx)
+                                }
+                            },
+                            SmxLayerKind::Shadow => SmxPixel::Shadow(try!(commands.read_u8())),
+                            SmxLayerKind::Outline => SmxPixel::Outline
+                        };
+
+                        if x >= width as usize {
+                            return Err(row_overflow(y));
+                        }
+                        pixels[row + x] = px;
+                        x += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(pixels.into_boxed_slice())
+}
+
+/// Reads the palette index of the `n`th packed pixel.
+fn unpack(data: &[u8], n: usize, packed_8to5: bool) -> Option<u16> {
+    if packed_8to5 {
+        let block = match data.get(n / 2 * 5..n / 2 * 5 + 5) {
+            Some(block) => block,
+            None => return None
+        };
+        let bits = block.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let px = (bits >> ((n % 2) * 20)) & 0xfffff;
+
+        Some((px & 0x3ff) as u16)
+    } else {
+        let block = match data.get(n / 4 * 5..n / 4 * 5 + 5) {
+            Some(block) => block,
+            None => return None
+        };
+        let section = (block[4] >> ((n % 4) * 2)) & 0x03;
+
+        Some(((section as u16) << 8) | block[n % 4] as u16)
+    }
+}
+
+fn truncated() -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, "SMX layer data is truncated")
+}
+
+/// Error for row `y` of a layer drawing past the layer's width.
+pub fn row_overflow(y: usize) -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::InvalidData, format!("row {} is wider than its layer", y))
+}
+
+/// Error for a frame index past the end of an SMX or SMP file.
+pub fn frame_not_found(index: usize) -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::InvalidInput, format!("frame {} not found", index))
+}
+
+/// Rasterizes SMX and SMP frames into the same planes as
+/// `SlpLayeredRasterizer`, on a canvas covering all layers, see
+/// `SmxFrame::bounds`.
+pub struct SmxRasterizer {
+}
+
+impl SmxRasterizer {
+    /// Fails with `PaletteIndex` if a main layer pixel is past the end of
+    /// `pal`, and with `BadDimensions` if a layer's pixels don't match its
+    /// size or the canvas is too large.
+    pub fn rasterize(frame: &SmxFrame, pal: &Palette) -> Result<SlpLayers, SlpError> {
+        for layer in frame.layers.iter() {
+            if (layer.width as usize).checked_mul(layer.height as usize) != Some(layer.pixels.len()) {
+                return Err(SlpError::BadDimensions(layer.width as i32, layer.height as i32));
+            }
+        }
+
+        let (width, height, hotspot) = try!(frame.bounds().ok_or(SlpError::BadDimensions(-1, -1)));
+        let len = try!((width as usize).checked_mul(height as usize)
+            .and_then(|len| len.checked_mul(4).map(|_| len))
+            .ok_or(SlpError::BadDimensions(width as i32, height as i32)));
+
+        let mut layers = SlpLayers {
+            width: width as _,
+            height: height as _,
+            color: vec![0u8; len * 4].into_boxed_slice(),
+            player: vec![0u8; len].into_boxed_slice(),
+            player_mask: vec![0u8; len].into_boxed_slice(),
+            shadow: vec![0u8; len].into_boxed_slice(),
+            outline: vec![0u8; len].into_boxed_slice(),
+            transparency: vec![0u8; len].into_boxed_slice()
+        };
+
+        for layer in frame.layers.iter().filter(|layer| layer.width > 0) {
+            // Never negative, and the layer fits in the canvas, since
+            // `bounds` takes the largest extents.
+            let dx = (hotspot.0 as i64 - layer.hotspot.0 as i64) as usize;
+            let dy = (hotspot.1 as i64 - layer.hotspot.1 as i64) as usize;
+
+            for (i, &px) in layer.pixels.iter().enumerate() {
+                let x = dx + i % layer.width as usize;
+                let y = dy + i / layer.width as usize;
+                let pos = y * width as usize + x;
+
+                match px {
+                    SmxPixel::Transparent => continue,
+                    SmxPixel::Color(idx) => {
+                        let col = try!(pal.colors.get(idx as usize).cloned().ok_or(SlpError::PaletteIndex(idx as usize)));
+                        layers.color[pos * 4..pos * 4 + 4].copy_from_slice(&[col.0, col.1, col.2, 0xff]);
+                    },
+                    SmxPixel::Player(idx) => {
+                        layers.player[pos] = idx as u8;
+                        layers.player_mask[pos] = 0xff;
+                    },
+                    SmxPixel::Shadow(value) => {
+                        layers.shadow[pos] = value;
+                    },
+                    SmxPixel::Outline => {
+                        layers.outline[pos] = OUTLINE_PLAYER;
+                    }
+                }
+            }
+
+            // Like the SLP outline table, the transparency mask covers
+            // each row of the main layer from its first to its last pixel.
+            if layer.kind == SmxLayerKind::Main {
+                for (y, row) in layer.pixels.chunks(layer.width as usize).enumerate() {
+                    let first = row.iter().position(|&px| px != SmxPixel::Transparent);
+                    let last = row.iter().rposition(|&px| px != SmxPixel::Transparent);
+
+                    if let (Some(first), Some(last)) = (first, last) {
+                        let start = (dy + y) * width as usize + dx;
+                        for pos in (start + first)..(start + last + 1) {
+                            layers.transparency[pos] = 0xff;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(layers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use format::pal::Palette;
+    use format::slp::SlpError;
+    use super::*;
+
+    fn layer(kind: SmxLayerKind, width: u32, height: u32, hotspot: (i32, i32), pixels: Vec<SmxPixel>) -> SmxLayer {
+        SmxLayer {
+            kind: kind,
+            width: width,
+            height: height,
+            hotspot: hotspot,
+            pixels: pixels.into_boxed_slice()
+        }
+    }
+
+    fn palette() -> Palette {
+        Palette {
+            colors: vec![(0, 0, 0), (10, 20, 30)].into_boxed_slice()
+        }
+    }
+
+    #[test]
+    fn rasterize() {
+        let frame = SmxFrame {
+            palette: 0,
+            layers: vec![
+                layer(SmxLayerKind::Main, 2, 1, (0, 0), vec![SmxPixel::Color(1), SmxPixel::Player(3)]),
+                layer(SmxLayerKind::Shadow, 1, 1, (-1, -1), vec![SmxPixel::Shadow(7)]),
+                layer(SmxLayerKind::Outline, 0, 0, (5, 5), vec![])
+            ]
+        };
+
+        assert_eq!(frame.bounds(), Some((7, 7, (5, 5))));
+
+        let layers = SmxRasterizer::rasterize(&frame, &palette()).unwrap();
+        let pos = 5 * 7 + 5;
+        assert_eq!(&layers.color[pos * 4..pos * 4 + 4], &[10, 20, 30, 0xff]);
+        assert_eq!((layers.player[pos + 1], layers.player_mask[pos + 1]), (3, 0xff));
+        assert_eq!(layers.shadow[6 * 7 + 6], 7);
+        assert_eq!(&layers.transparency[pos..pos + 3], &[0xff, 0xff, 0]);
+    }
+
+    #[test]
+    fn rasterize_bad_layers() {
+        let frame = SmxFrame {
+            palette: 0,
+            layers: vec![layer(SmxLayerKind::Main, 1, 1, (0, 0), vec![SmxPixel::Color(2)])]
+        };
+        match SmxRasterizer::rasterize(&frame, &palette()) {
+            Err(SlpError::PaletteIndex(2)) => {},
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+
+        let frame = SmxFrame {
+            palette: 0,
+            layers: vec![layer(SmxLayerKind::Main, 2, 2, (0, 0), vec![SmxPixel::Transparent])]
+        };
+        match SmxRasterizer::rasterize(&frame, &palette()) {
+            Err(SlpError::BadDimensions(2, 2)) => {},
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+
+        let frame = SmxFrame {
+            palette: 0,
+            layers: vec![
+                layer(SmxLayerKind::Main, 0, 0, (i32::max_value(), 0), vec![]),
+                layer(SmxLayerKind::Shadow, 1, 1, (i32::min_value(), 0), vec![SmxPixel::Shadow(1)])
+            ]
+        };
+        assert_eq!(frame.bounds(), None);
+    }
+
+    #[test]
+    fn layer_limits() {
+        assert_eq!(layer_len(MAX_LAYER_SIZE, 2).unwrap(), MAX_LAYER_SIZE as usize * 2);
+        assert!(layer_len(MAX_LAYER_SIZE + 1, 1).is_err());
+        assert!(layer_len(1, u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn frame_out_of_range() {
+        let mut data = b"SMPX".to_vec();
+        data.extend_from_slice(&[0; 2 + 2 + 4 + 4 + 16]);
+
+        let mut reader = SmxReader::new(::std::io::Cursor::new(data)).unwrap();
+        assert_eq!(reader.len(), 0);
+        assert_eq!(reader.read_frame(0).unwrap_err().kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+
+    /// Layer data: row edges, commands and, for the main layer, packed
+    /// pixels.
+    fn layer_data(edges: &[(u16, u16)], commands: &[u8], pixels: Option<&[u8]>) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &(left, right) in edges {
+            data.write_u16::<LittleEndian>(left).unwrap();
+            data.write_u16::<LittleEndian>(right).unwrap();
+        }
+        data.write_u32::<LittleEndian>(commands.len() as u32).unwrap();
+        data.extend_from_slice(commands);
+        if let Some(pixels) = pixels {
+            data.write_u32::<LittleEndian>(pixels.len() as u32).unwrap();
+            data.extend_from_slice(pixels);
+        }
+        data
+    }
+
+    /// An SMX file with one frame of type `ty` holding `layers`, each as
+    /// width, height, hotspot and data.
+    fn smx(ty: u8, layers: &[(u16, u16, (i16, i16), Vec<u8>)]) -> Vec<u8> {
+        let mut data = b"SMPX".to_vec();
+        data.write_u16::<LittleEndian>(2).unwrap();
+        data.write_u16::<LittleEndian>(1).unwrap();
+        data.extend_from_slice(&[0; 4 + 4 + 16]);
+
+        data.push(ty);
+        data.push(21);
+        data.write_u32::<LittleEndian>(0).unwrap();
+        for &(width, height, hotspot, ref layer) in layers {
+            data.write_u16::<LittleEndian>(width).unwrap();
+            data.write_u16::<LittleEndian>(height).unwrap();
+            data.write_i16::<LittleEndian>(hotspot.0).unwrap();
+            data.write_i16::<LittleEndian>(hotspot.1).unwrap();
+            data.write_u32::<LittleEndian>(layer.len() as u32).unwrap();
+            data.write_u32::<LittleEndian>(0).unwrap();
+            data.extend_from_slice(layer);
+        }
+        data
+    }
+
+    /// Main layer commands: a color and a player pixel, then a skip, then
+    /// two colors, then an empty row.
+    const MAIN_EDGES: [(u16, u16); 3] = [(0, 1), (1, 0), (0xffff, 0xffff)];
+    const MAIN_COMMANDS: [u8; 6] = [0x01, 0x02, 0x03, 0x05, 0x03, 0x03];
+
+    fn main_pixels() -> Vec<SmxPixel> {
+        use super::SmxPixel::*;
+        vec![Color(0x105), Player(7), Transparent, Transparent, Color(0x2ff), Color(0x010), Transparent, Transparent, Transparent]
+    }
+
+    fn read(data: Vec<u8>) -> Result<SmxFrame, ::std::io::Error> {
+        let mut reader = try!(SmxReader::new(Cursor::new(data)));
+        assert_eq!(reader.len(), 1);
+        reader.read_frame(0)
+    }
+
+    #[test]
+    fn read_4plus1() {
+        // Indices 0x05, 0x07, 0xff and 0x10, with sections 1, 0, 2 and 0.
+        let pixels = [0x05, 0x07, 0xff, 0x10, 0x21];
+        let shadow = layer_data(&[(0, 0)], &[0x05, 0x40, 0x80, 0x03], None);
+        let outline = layer_data(&[(1, 0)], &[0x01, 0x03], None);
+
+        let frame = read(smx(FRAME_MAIN | FRAME_SHADOW | FRAME_OUTLINE, &[
+            (3, 3, (1, 2), layer_data(&MAIN_EDGES, &MAIN_COMMANDS, Some(&pixels))),
+            (2, 1, (0, 0), shadow),
+            (2, 1, (-1, 0), outline)
+        ])).unwrap();
+
+        assert_eq!(frame.palette, 21);
+        let main = frame.layer(SmxLayerKind::Main).unwrap();
+        assert_eq!((main.width, main.height, main.hotspot), (3, 3, (1, 2)));
+        assert_eq!(&main.pixels[..], &main_pixels()[..]);
+        assert_eq!(&frame.layer(SmxLayerKind::Shadow).unwrap().pixels[..], &[SmxPixel::Shadow(0x40), SmxPixel::Shadow(0x80)]);
+        assert_eq!(&frame.layer(SmxLayerKind::Outline).unwrap().pixels[..], &[SmxPixel::Transparent, SmxPixel::Outline]);
+    }
+
+    #[test]
+    fn read_8to5() {
+        // Two 20-bit pixels per 5 bytes, with damage bits set above the
+        // 10-bit indices.
+        let mut pixels = Vec::new();
+        for &(a, b) in &[(0x105u64, 0x007u64), (0x2ff, 0x010)] {
+            let bits = (a | 0xfc00) | ((b | 0x8c00) << 20);
+            pixels.extend_from_slice(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8, (bits >> 32) as u8]);
+        }
+
+        let frame = read(smx(FRAME_MAIN | FRAME_8TO5, &[
+            (3, 3, (0, 0), layer_data(&MAIN_EDGES, &MAIN_COMMANDS, Some(&pixels)))
+        ])).unwrap();
+
+        assert_eq!(frame.layers.len(), 1);
+        assert_eq!(&frame.layers[0].pixels[..], &main_pixels()[..]);
+    }
+
+    #[test]
+    fn read_bad_layers() {
+        // Too few packed pixels.
+        let data = smx(FRAME_MAIN, &[(3, 3, (0, 0), layer_data(&MAIN_EDGES, &MAIN_COMMANDS, Some(&[0; 4])))]);
+        assert_eq!(read(data).unwrap_err().kind(), ::std::io::ErrorKind::UnexpectedEof);
+
+        // Two pixels in a row one pixel wide.
+        let data = smx(FRAME_SHADOW, &[(1, 1, (0, 0), layer_data(&[(0, 0)], &[0x05, 1, 2, 0x03], None))]);
+        assert_eq!(read(data).unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+
+        // A layer claiming far more data than the file holds.
+        let mut data = smx(FRAME_OUTLINE, &[(1, 1, (0, 0), layer_data(&[(0, 0)], &[0x01, 0x03], None))]);
+        let len = data.len();
+        (&mut data[len - 10 - 8..len - 10 - 4]).write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        assert_eq!(read(data).unwrap_err().kind(), ::std::io::ErrorKind::UnexpectedEof);
+    }
+}
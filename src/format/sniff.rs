@@ -0,0 +1,265 @@
+use byteorder::{LittleEndian, ByteOrder};
+
+/// Format of a resource as detected from its contents.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentType {
+    /// JASC palette, readable with `pal::Palette::parse`.
+    Palette,
+    /// Windows bitmap.
+    Bitmap,
+    /// Interface screen definition (`.sin`), listing the backgrounds,
+    /// palette and other resources used by a screen.
+    ScreenInfo,
+    /// Other plain text, such as string tables.
+    Text,
+    /// SLP sprite with its version string, e.g. `2.0N`.
+    Slp(String),
+    Wav(WavInfo),
+    Unknown
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WavInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16
+}
+
+/// Keys that only appear in interface screen definitions.
+const SCREEN_INFO_KEYS: &'static [&'static str] = &[
+    "background1_files",
+    "background2_files",
+    "background3_files",
+    "palette_file",
+    "cursor_file",
+    "shade_amount",
+    "button_file",
+    "popup_dialog_sin_file"
+];
+
+/// Inspects the payload of a resource to find out what it contains.
+pub fn sniff(data: &[u8]) -> ContentType {
+    if data.starts_with(b"JASC-PAL") {
+        return ContentType::Palette;
+    }
+
+    if let Some(version) = sniff_slp(data) {
+        return ContentType::Slp(version);
+    }
+
+    if data.starts_with(b"RIFF") {
+        return match sniff_wav(data) {
+            Some(info) => ContentType::Wav(info),
+            None => ContentType::Unknown
+        };
+    }
+
+    if data.len() >= 14 && data.starts_with(b"BM") && LittleEndian::read_u32(&data[2..6]) as usize == data.len() {
+        return ContentType::Bitmap;
+    }
+
+    if !data.is_empty() && is_text(data) {
+        let text = String::from_utf8_lossy(data);
+        let screen_info = text.lines().any(|line| {
+            line.split_whitespace().next().map_or(false, |key| SCREEN_INFO_KEYS.contains(&key))
+        });
+
+        return if screen_info {
+            ContentType::ScreenInfo
+        } else {
+            ContentType::Text
+        };
+    }
+
+    ContentType::Unknown
+}
+
+fn sniff_slp(data: &[u8]) -> Option<String> {
+    if data.len() < 32 {
+        return None;
+    }
+
+    let version = &data[0..4];
+    let known = version[0].is_ascii_digit() && version[1] == b'.' && version[2].is_ascii_digit();
+    if !known {
+        return None;
+    }
+
+    // Every frame has a 32 byte entry following the header. `4.x` files
+    // have a 16-bit count followed by the sprite type.
+    let count = if version[0] >= b'4' {
+        LittleEndian::read_u16(&data[4..6]) as usize
+    } else {
+        LittleEndian::read_u32(&data[4..8]) as usize
+    };
+    if count == 0 || count > (data.len() - 32) / 32 {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(version).trim_right_matches('\0').to_string())
+}
+
+fn sniff_wav(data: &[u8]) -> Option<WavInfo> {
+    if data.len() < 12 || &data[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let riff_size = LittleEndian::read_u32(&data[4..8]) as usize;
+    if riff_size > data.len() - 8 {
+        return None;
+    }
+
+    let mut pos = 12;
+    while pos + 8 <= riff_size + 8 {
+        let id = &data[pos..pos + 4];
+        let size = LittleEndian::read_u32(&data[pos + 4..pos + 8]) as usize;
+        let body = pos + 8;
+
+        if size > data.len() - body {
+            return None;
+        }
+
+        if id == b"fmt " && size >= 16 {
+            return Some(WavInfo {
+                channels: LittleEndian::read_u16(&data[body + 2..body + 4]),
+                sample_rate: LittleEndian::read_u32(&data[body + 4..body + 8]),
+                bits_per_sample: LittleEndian::read_u16(&data[body + 14..body + 16])
+            });
+        }
+
+        // Chunks are padded to an even size.
+        pos = body + size + (size & 1);
+    }
+
+    None
+}
+
+fn is_text(data: &[u8]) -> bool {
+    data.iter().all(|&b| b == b'\r' || b == b'\n' || b == b'\t' || b == 0x1a || (b >= 0x20 && b != 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use format::slp::{SlpWriter, SlpFrame, SlpPixel};
+    use super::*;
+
+    fn wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for &(id, data) in chunks {
+            body.extend_from_slice(id);
+            body.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+
+        let mut out = b"RIFF".to_vec();
+        out.write_u32::<LittleEndian>(body.len() as u32).unwrap();
+        out.extend_from_slice(&body);
+        out
+    }
+
+    fn fmt(channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.write_u16::<LittleEndian>(1).unwrap();
+        fmt.write_u16::<LittleEndian>(channels).unwrap();
+        fmt.write_u32::<LittleEndian>(sample_rate).unwrap();
+        fmt.write_u32::<LittleEndian>(sample_rate * channels as u32 * bits as u32 / 8).unwrap();
+        fmt.write_u16::<LittleEndian>(channels * bits / 8).unwrap();
+        fmt.write_u16::<LittleEndian>(bits).unwrap();
+        fmt
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(sniff(b"JASC-PAL\r\n0100\r\n256\r\n0 0 0\r\n"), ContentType::Palette);
+    }
+
+    #[test]
+    fn bitmap() {
+        let mut bmp = b"BM".to_vec();
+        bmp.write_u32::<LittleEndian>(20).unwrap();
+        bmp.extend_from_slice(&[0; 14]);
+        assert_eq!(sniff(&bmp), ContentType::Bitmap);
+
+        // The size in the header must match.
+        bmp.push(0);
+        assert_eq!(sniff(&bmp), ContentType::Unknown);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(sniff(b"background1_files  btn_main none -1\r\npalette_file pal.pal 50500\r\n"), ContentType::ScreenInfo);
+        assert_eq!(sniff(b"shade_amount 0\n"), ContentType::ScreenInfo);
+        assert_eq!(sniff(b"1001 \"Hello\"\r\n1002 \"World\"\r\n\x1a"), ContentType::Text);
+        assert_eq!(sniff(b"not palette_file\n"), ContentType::Text);
+        assert_eq!(sniff(b"text\0with a nul"), ContentType::Unknown);
+        assert_eq!(sniff(b""), ContentType::Unknown);
+    }
+
+    #[test]
+    fn slp() {
+        let mut writer = SlpWriter::new();
+        writer.add_frame(SlpFrame {
+            width: 2,
+            height: 2,
+            hotspot: (0, 0),
+            pixels: vec![SlpPixel::Color(1); 4].into_boxed_slice()
+        });
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        assert_eq!(sniff(&data), ContentType::Slp("2.0N".to_string()));
+
+        // A 4.x header with a 16-bit count and a sprite type.
+        let mut v4 = data.clone();
+        v4[..4].copy_from_slice(b"4.0X");
+        (&mut v4[4..8]).write_u32::<LittleEndian>(0x0008_0001).unwrap();
+        assert_eq!(sniff(&v4), ContentType::Slp("4.0X".to_string()));
+
+        // No frames, or more frames than the data holds.
+        let mut bad = data.clone();
+        (&mut bad[4..8]).write_u32::<LittleEndian>(0).unwrap();
+        assert_eq!(sniff(&bad), ContentType::Unknown);
+        (&mut bad[4..8]).write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        assert_eq!(sniff(&bad), ContentType::Unknown);
+    }
+
+    #[test]
+    fn wave() {
+        let info = WavInfo { channels: 2, sample_rate: 22050, bits_per_sample: 16 };
+
+        // The format chunk may follow other chunks, padded to even sizes.
+        let data = wav(&[(b"LIST", b"odd"), (b"fmt ", &fmt(2, 22050, 16)), (b"data", &[0; 8])]);
+        assert_eq!(sniff(&data), ContentType::Wav(info));
+    }
+
+    #[test]
+    fn malformed_riff() {
+        // Not a WAVE file.
+        let mut avi = wav(&[(b"fmt ", &fmt(1, 11025, 8))]);
+        avi[8..12].copy_from_slice(b"AVI ");
+        assert_eq!(sniff(&avi), ContentType::Unknown);
+
+        // RIFF size past the end of the data.
+        let mut data = wav(&[(b"fmt ", &fmt(1, 11025, 8))]);
+        (&mut data[4..8]).write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        assert_eq!(sniff(&data), ContentType::Unknown);
+
+        // Chunk size past the end of the data.
+        let mut data = wav(&[(b"data", &[0; 4]), (b"fmt ", &fmt(1, 11025, 8))]);
+        (&mut data[16..20]).write_u32::<LittleEndian>(u32::max_value() - 4).unwrap();
+        assert_eq!(sniff(&data), ContentType::Unknown);
+
+        // Format chunk too short, and no format chunk.
+        assert_eq!(sniff(&wav(&[(b"fmt ", &[0; 8])])), ContentType::Unknown);
+        assert_eq!(sniff(&wav(&[(b"data", &[0; 8])])), ContentType::Unknown);
+        assert_eq!(sniff(b"RIFF"), ContentType::Unknown);
+    }
+
+    #[test]
+    fn unknown() {
+        assert_eq!(sniff(&[0, 1, 2, 3, 0xff]), ContentType::Unknown);
+    }
+}
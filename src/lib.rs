@@ -0,0 +1,11 @@
+extern crate byteorder;
+extern crate image;
+extern crate memmap;
+extern crate png;
+extern crate gif;
+extern crate deflate;
+extern crate num_cpus;
+
+pub mod format;
+pub mod resource;
+pub mod task;
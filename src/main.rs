@@ -1,11 +1,9 @@
 extern crate inflate;
-extern crate byteorder;
 extern crate image;
+extern crate ae;
 
-mod format;
-
-use format::{drs, slp, dat, pal};
-use format::slp::SlpRasterizer;
+use ae::format::{drs, slp, dat, pal};
+use ae::task;
 
 use std::io::{BufReader, Cursor, Write};
 use std::fs::File;
@@ -48,26 +46,19 @@ fn dump_slp(file: &Path, out: &Path, pal: &pal::Palette) {
     let mut reader = BufReader::new(File::open(file).unwrap());
 
     let mut reader = slp::SlpReader::new(&mut reader).unwrap();
+    let table = slp::SlpColorTable::new(pal, &slp::SlpRasterOptions::default()).unwrap();
     let mut slps = Vec::new();
 
-    let mut bytes = 0;
-
-    let start = ::std::time::Instant::now();
+    // See `benches/rasterize.rs` for timings.
     for i in 0..reader.len() {
         let obj = reader.read_shape(i).unwrap();
 
-        bytes += obj.width * obj.height * 4;
-        let mut buf = vec![0u8; (obj.width * obj.height * 4) as _];
-        let mut cur = Cursor::new(buf.into_boxed_slice());
-        slp::SlpDefaultRasterizer::rasterize(&mut cur, &obj, &pal);
+        let mut buf = vec![0u8; obj.buffer_len(4).unwrap()];
+        slp::SlpFastRasterizer::rasterize(&mut buf, obj.width as usize * 4, &obj, &table).unwrap();
 
-        slps.push((obj, cur.into_inner()));
+        slps.push((obj, buf));
     }
 
-    let time = ::std::time::Instant::now().duration_since(start);
-    let secs = time.as_secs() as f64 + (time.subsec_nanos() as f64) / 1000000000f64;
-    println!("Rasterized {} SLP ({}) in {:.3} ms: {:.3} SLP/s, {:.3} MiB/s", reader.len(), file.file_name().unwrap().to_str().unwrap(), time.as_secs() as f64 * 1000f64 + (time.subsec_nanos() as f64) / 1000000f64, reader.len() as f64 / secs, bytes as f64 / 1024f64 / 1024f64 / secs);
-
     for (i, (obj, buf)) in slps.into_iter().enumerate() {
         use image::png::PNGEncoder;
         use image::ColorType;
@@ -79,9 +70,79 @@ fn dump_slp(file: &Path, out: &Path, pal: &pal::Palette) {
     }
 }
 
+/// Prints the commands of each frame, or only of frame `frame`.
+fn disasm_slp(file: &Path, frame: Option<usize>) {
+    let mut reader = BufReader::new(File::open(file).unwrap());
+    let mut reader = slp::SlpReader::new(&mut reader).unwrap();
+
+    let stdout = ::std::io::stdout();
+    let mut out = stdout.lock();
+
+    let frames = match frame {
+        Some(frame) => frame..frame + 1,
+        None => 0..reader.len()
+    };
+
+    for i in frames {
+        writeln!(out, "# {} frame {}", file.display(), i).unwrap();
+
+        let res = reader.read_shape(i).and_then(|obj| slp::write_disassembly(&mut out, &obj));
+        if let Err(err) = res {
+            writeln!(out, "error: {}", err).unwrap();
+        }
+    }
+}
+
+/// Packs all frames into texture atlases with a JSON manifest in `out`.
+fn atlas_slp(file: &Path, out: &Path, pal: &pal::Palette) {
+    let mut reader = BufReader::new(File::open(file).unwrap());
+    let mut reader = slp::SlpReader::new(&mut reader).unwrap();
+
+    let atlas = slp::SlpAtlas::build(&mut reader, pal, &slp::SlpRasterOptions::default(), &slp::SlpAtlasOptions::default()).unwrap();
+    atlas.save(out, file.file_stem().unwrap().to_str().unwrap()).unwrap();
+}
+
+/// Like `dump_slp` for many files, rasterizing all their frames in
+/// parallel.
+fn dump_slps(files: &[::std::path::PathBuf], out: &Path, pal: &pal::Palette) {
+    use std::io::Read;
+
+    let data = files.iter().map(|file| {
+        let mut data = Vec::new();
+        File::open(file).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }).collect();
+
+    let jobs = task::JobSystem::with_cpus();
+    let results = slp::rasterize_batch(&jobs, data, pal, &slp::SlpRasterOptions::default()).unwrap();
+
+    for (file, frames) in files.iter().zip(results) {
+        let name = file.file_name().unwrap().to_str().unwrap();
+
+        match frames {
+            Ok(frames) => for (i, frame) in frames.into_iter().enumerate() {
+                frame.image.save(out.join(format!("{}-{}.png", name, i))).unwrap();
+            },
+            Err(err) => println!("{}: {}", name, err)
+        }
+    }
+}
+
 // 133 x 306
 
 fn main() {
+    let args: Vec<String> = ::std::env::args().collect();
+    if args.len() >= 4 && args[1] == "slp" && args[2] == "disasm" {
+        let frame = args.get(4).map(|frame| frame.parse().unwrap());
+        disasm_slp(Path::new(&args[3]), frame);
+        return;
+    }
+    if args.len() >= 6 && args[1] == "slp" && args[2] == "atlas" {
+        let pal = pal::Palette::parse(&mut BufReader::new(File::open(&args[5]).unwrap())).unwrap();
+        atlas_slp(Path::new(&args[3]), Path::new(&args[4]), &pal);
+        return;
+    }
+
     let pal = {
         let mut reader = BufReader::new(File::open("C:/Program Files (x86)/Steam/steamapps/common/Age2HD/resources/_common/drs/interface/50500.bina").unwrap());
         pal::Palette::parse(&mut reader).unwrap()
@@ -95,23 +156,21 @@ fn main() {
 
     let files = ::std::fs::read_dir("C:/Program Files (x86)/Steam/steamapps/common/Age2HD/resources/_common/drs/interface/").unwrap();
 
+    let mut paths = Vec::new();
     for path in files {
         if let Ok(path) = path {
             if path.file_type().unwrap().is_file() {
-                let buf = path.path();
-                let path = buf.as_path();
+                let path = path.path();
                     if path.extension().unwrap().to_str().unwrap() == "slp" {
-                        //let name = format!("./dump/slp/{}/", path.file_name().unwrap().to_str().unwrap());
-                        let name = "./dump/slp/";
-                        let dest = Path::new(&name);
-                        //::std::fs::create_dir(dest).unwrap();
-                        dump_slp(path, dest, &pal);
+                        paths.push(path);
                     }
             }
 
 
         }
     }
+
+    dump_slps(&paths, Path::new("./dump/slp/"), &pal);
     //datfile();
 
 /*
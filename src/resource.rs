@@ -0,0 +1,192 @@
+use format::drs::{DrsReader, DrsFile, DrsExt, DrsError};
+
+use std::io::{Read, BufReader, ErrorKind};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+enum Source {
+    Drs(DrsReader<BufReader<File>>),
+    Dir(PathBuf)
+}
+
+struct Layer {
+    name: String,
+    source: Source
+}
+
+/// A file resolved by a `ResourceSet`, along with the layer it came from.
+#[derive(Debug)]
+pub struct Resource {
+    pub layer: usize,
+    pub name: String,
+    pub file: DrsFile
+}
+
+/// Resolves resources by id over a stack of DRS archives and loose
+/// directories, the way the game searches its data files.
+///
+/// Layers added later take priority over earlier ones, so the base game
+/// should be added first, followed by expansions and then mods. Directories
+/// hold files named after their id and extension, e.g. `53101.slp`.
+pub struct ResourceSet {
+    layers: Vec<Layer>
+}
+
+impl ResourceSet {
+    pub fn new() -> ResourceSet {
+        ResourceSet {
+            layers: Vec::new()
+        }
+    }
+
+    /// Adds a DRS archive on top of the existing layers.
+    pub fn add_drs<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DrsError> {
+        let path = path.as_ref();
+        let reader = BufReader::new(try!(File::open(path)));
+
+        self.layers.push(Layer {
+            name: path.display().to_string(),
+            source: Source::Drs(try!(DrsReader::new(reader)))
+        });
+
+        Ok(())
+    }
+
+    /// Adds a directory of loose files on top of the existing layers.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+
+        self.layers.push(Layer {
+            name: path.display().to_string(),
+            source: Source::Dir(path.to_path_buf())
+        });
+    }
+
+    /// Names of all layers, from lowest to highest priority.
+    pub fn layers(&self) -> Vec<&str> {
+        self.layers.iter().map(|layer| layer.name.as_str()).collect()
+    }
+
+    /// Reads a resource from the highest priority layer that contains it.
+    pub fn read(&mut self, ext: DrsExt, id: u32) -> Result<Resource, DrsError> {
+        for (index, layer) in self.layers.iter_mut().enumerate().rev() {
+            let file = match layer.source {
+                Source::Drs(ref mut reader) => {
+                    match reader.read_file_ext(ext, id as _) {
+                        Ok(file) => file,
+                        Err(DrsError::ExtNotFound(_)) | Err(DrsError::EntryNotFound(_)) => continue,
+                        Err(err) => return Err(err)
+                    }
+                },
+                Source::Dir(ref path) => {
+                    let path = path.join(format!("{}.{}", id, ext.name()));
+                    let mut file = match File::open(path) {
+                        Ok(file) => file,
+                        Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                        Err(err) => return Err(DrsError::Io(err))
+                    };
+
+                    let mut data = Vec::new();
+                    try!(file.read_to_end(&mut data));
+                    DrsFile::new(ext, data.into_boxed_slice())
+                }
+            };
+
+            return Ok(Resource {
+                layer: index,
+                name: layer.name.clone(),
+                file: file
+            });
+        }
+
+        Err(DrsError::EntryNotFound(id as _))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use format::drs::{DrsWriter, DrsExt, DrsError};
+    use super::*;
+
+    /// A scratch directory removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("ae-resource-{}-{}", name, ::std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn write(&self, name: &str, data: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            File::create(&path).unwrap().write_all(data).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn layer_priority() {
+        let root = TempDir::new("priority");
+
+        let mut writer = DrsWriter::new();
+        writer.add_raw(DrsExt::Slp.to_u32(), 1, b"drs 1".to_vec().into_boxed_slice());
+        writer.add_raw(DrsExt::Slp.to_u32(), 2, b"drs 2".to_vec().into_boxed_slice());
+        writer.add_raw(DrsExt::Wav.to_u32(), 2, b"drs wav".to_vec().into_boxed_slice());
+        let mut drs = Vec::new();
+        writer.write(&mut drs).unwrap();
+        let drs_path = root.write("base.drs", &drs);
+
+        let low = TempDir::new("low");
+        low.write("1.slp", b"low 1");
+        low.write("3.slp", b"low 3");
+
+        let high = TempDir::new("high");
+        high.write("2.slp", b"high 2");
+        high.write("4.slp", b"high 4");
+
+        let mut set = ResourceSet::new();
+        set.add_dir(&low.0);
+        set.add_drs(&drs_path).unwrap();
+        set.add_dir(&high.0);
+
+        let names: Vec<String> = vec![low.0.display().to_string(), drs_path.display().to_string(), high.0.display().to_string()];
+        assert_eq!(set.layers(), names.iter().map(|name| name.as_str()).collect::<Vec<_>>());
+
+        for &(ext, id, layer, data) in &[
+            // The archive covers the directory below it...
+            (DrsExt::Slp, 1, 1, &b"drs 1"[..]),
+            // ...and is covered by the one above.
+            (DrsExt::Slp, 2, 2, &b"high 2"[..]),
+            // Files only in one layer come from that layer.
+            (DrsExt::Slp, 3, 0, &b"low 3"[..]),
+            (DrsExt::Slp, 4, 2, &b"high 4"[..]),
+            // Same id, other extension.
+            (DrsExt::Wav, 2, 1, &b"drs wav"[..])
+        ] {
+            let resource = set.read(ext, id).unwrap();
+            assert_eq!((resource.layer, resource.name.as_str()), (layer, names[layer].as_str()));
+            assert_eq!(&resource.file.into_data()[..], data);
+        }
+
+        // Missing ids, and extensions without a table in the archive.
+        match set.read(DrsExt::Slp, 5) {
+            Err(DrsError::EntryNotFound(5)) => {},
+            other => panic!("{:?}", other)
+        }
+        match set.read(DrsExt::Bin, 1) {
+            Err(DrsError::EntryNotFound(1)) => {},
+            other => panic!("{:?}", other)
+        }
+    }
+}
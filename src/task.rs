@@ -1,17 +1,300 @@
-pub struct Task<'a, T: 'a + Copy + Clone> {
-    func: fn(T),
-    parent: Option<&'a mut Task<'a, T>>,
-    unfinished: i32,
-    data: [u8; 52],
+//! A small work-stealing job system.
+//!
+//! Each worker thread has its own queue. Tasks spawned by a worker go to the
+//! back of its queue and are taken from the back again, while idle workers
+//! steal from the front of other queues. A task may spawn children and is
+//! only finished once they all are, which in turn finishes its parent.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread::{self, JoinHandle};
+
+use num_cpus;
+
+pub struct Task {
+    func: Box<FnOnce(&Worker) + Send>,
+    state: Arc<TaskState>
+}
+
+struct TaskState {
+    parent: Option<Arc<TaskState>>,
+    /// The task itself and each of its unfinished children.
+    unfinished: AtomicUsize
+}
+
+impl TaskState {
+    fn finish(&self) {
+        // Sequentially consistent along with `Shared::waiting`, so that a
+        // thread in `JobSystem::wait` either sees the task finished or is
+        // counted before `Shared::run` checks for waiters.
+        if self.unfinished.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(ref parent) = self.parent {
+                parent.finish();
+            }
+        }
+    }
+}
+
+/// Tracks the completion of a spawned task and its children.
+#[derive(Clone)]
+pub struct TaskHandle {
+    state: Arc<TaskState>
+}
+
+impl TaskHandle {
+    pub fn is_finished(&self) -> bool {
+        self.state.unfinished.load(Ordering::SeqCst) == 0
+    }
+}
+
+struct Shared {
+    /// One queue per worker thread, plus one for tasks spawned from outside
+    /// the workers.
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    /// Number of queued tasks, checked under `sleep` before waiting on
+    /// `wake`.
+    queued: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    /// Number of threads blocked in `JobSystem::wait`, which are woken
+    /// through `done` when a task finishes or is queued.
+    waiting: AtomicUsize,
+    done: Condvar,
+    shutdown: AtomicBool
+}
+
+impl Shared {
+    fn push(&self, queue: usize, task: Task) {
+        self.queued.fetch_add(1, Ordering::AcqRel);
+        self.queues[queue].lock().unwrap().push_back(task);
+
+        let _guard = self.sleep.lock().unwrap();
+        self.wake.notify_one();
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.done.notify_all();
+        }
+    }
+
+    /// Takes the newest task of queue `own`, or steals the oldest task of
+    /// another queue.
+    fn pop(&self, own: usize) -> Option<Task> {
+        let task = self.queues[own].lock().unwrap().pop_back().or_else(|| {
+            let len = self.queues.len();
+            (1..len).filter_map(|i| self.queues[(own + i) % len].lock().unwrap().pop_front()).next()
+        });
+
+        if task.is_some() {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+        }
+
+        task
+    }
+
+    fn run(&self, queue: usize, task: Task) {
+        let Task { func, state } = task;
+
+        {
+            let worker = Worker {
+                shared: self,
+                queue: queue,
+                current: state.clone()
+            };
+
+            // A panicking task still counts as finished, so that waiting on
+            // it does not hang and the worker thread survives.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| func(&worker)));
+        }
+
+        state.finish();
+
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            let _guard = self.sleep.lock().unwrap();
+            self.done.notify_all();
+        }
+    }
+}
+
+/// Passed to running tasks to spawn children.
+pub struct Worker<'a> {
+    shared: &'a Shared,
+    queue: usize,
+    current: Arc<TaskState>
+}
+
+impl<'a> Worker<'a> {
+    /// Spawns a child of the running task, which will not finish before the
+    /// child does.
+    pub fn spawn<F: FnOnce(&Worker) + Send + 'static>(&self, func: F) -> TaskHandle {
+        self.current.unfinished.fetch_add(1, Ordering::AcqRel);
+        spawn_task(self.shared, self.queue, Some(self.current.clone()), Box::new(func))
+    }
+}
+
+fn spawn_task(shared: &Shared, queue: usize, parent: Option<Arc<TaskState>>, func: Box<FnOnce(&Worker) + Send>) -> TaskHandle {
+    let state = Arc::new(TaskState {
+        parent: parent,
+        unfinished: AtomicUsize::new(1)
+    });
+
+    shared.push(queue, Task {
+        func: func,
+        state: state.clone()
+    });
+
+    TaskHandle {
+        state: state
+    }
+}
+
+pub struct JobSystem {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>
+}
+
+impl JobSystem {
+    /// Starts `threads` worker threads.
+    pub fn new(threads: usize) -> JobSystem {
+        let shared = Arc::new(Shared {
+            queues: (0..threads + 1).map(|_| Mutex::new(VecDeque::new())).collect(),
+            queued: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            waiting: AtomicUsize::new(0),
+            done: Condvar::new(),
+            shutdown: AtomicBool::new(false)
+        });
+
+        let threads = (0..threads).map(|i| {
+            let shared = shared.clone();
+            thread::spawn(move || worker_loop(&shared, i))
+        }).collect();
+
+        JobSystem {
+            shared: shared,
+            threads: threads
+        }
+    }
+
+    /// Starts a worker thread per CPU.
+    pub fn with_cpus() -> JobSystem {
+        JobSystem::new(num_cpus::get())
+    }
+
+    pub fn spawn<F: FnOnce(&Worker) + Send + 'static>(&self, func: F) -> TaskHandle {
+        let queue = self.threads.len();
+        spawn_task(&self.shared, queue, None, Box::new(func))
+    }
+
+    /// Blocks until `handle` and all its children have finished, running
+    /// queued tasks on the calling thread in the meantime.
+    pub fn wait(&self, handle: &TaskHandle) {
+        let queue = self.threads.len();
+
+        let shared = &self.shared;
+
+        while !handle.is_finished() {
+            if let Some(task) = shared.pop(queue) {
+                shared.run(queue, task);
+                continue;
+            }
+
+            let guard = shared.sleep.lock().unwrap();
+            shared.waiting.fetch_add(1, Ordering::SeqCst);
+            if !handle.is_finished() && shared.queued.load(Ordering::SeqCst) == 0 {
+                let _guard = shared.done.wait(guard).unwrap();
+            }
+            shared.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+
+        {
+            let _guard = self.shared.sleep.lock().unwrap();
+            self.shared.wake.notify_all();
+        }
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
 }
 
-impl<'a, T: Copy + Clone> Task<'a, T> {
-    pub fn with_data(func: fn(T), data: T) -> Task<'a, T> {
-        Task {
-            func: func,
-            parent: None,
-            unfinished: 0,
-            data: [0u8; 52]
+fn worker_loop(shared: &Shared, queue: usize) {
+    loop {
+        if let Some(task) = shared.pop(queue) {
+            shared.run(queue, task);
+            continue;
         }
+
+        let guard = shared.sleep.lock().unwrap();
+        if shared.shutdown.load(Ordering::Acquire) {
+            return;
+        }
+        if shared.queued.load(Ordering::Acquire) == 0 {
+            let _guard = shared.wake.wait(guard).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    #[test]
+    fn wait_for_children() {
+        for &threads in &[0, 1, 4] {
+            let jobs = JobSystem::new(threads);
+            let count = Arc::new(AtomicUsize::new(0));
+
+            let handle = {
+                let count = count.clone();
+                jobs.spawn(move |worker| {
+                    for _ in 0..50 {
+                        let count = count.clone();
+                        worker.spawn(move |worker| {
+                            let count = count.clone();
+                            worker.spawn(move |_| {
+                                count.fetch_add(1, Ordering::SeqCst);
+                            });
+                        });
+                    }
+                })
+            };
+
+            jobs.wait(&handle);
+            assert!(handle.is_finished());
+            assert_eq!(count.load(Ordering::SeqCst), 50);
+        }
+    }
+
+    #[test]
+    fn panicking_task_finishes() {
+        let jobs = JobSystem::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let handle = {
+            let count = count.clone();
+            jobs.spawn(move |worker| {
+                worker.spawn(|_| panic!("task panicked"));
+                worker.spawn(move |_| {
+                    count.fetch_add(1, Ordering::SeqCst);
+                });
+            })
+        };
+
+        jobs.wait(&handle);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // The workers survive the panic.
+        let handle = jobs.spawn(|_| {});
+        jobs.wait(&handle);
     }
 }